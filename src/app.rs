@@ -1,10 +1,105 @@
-use crate::chat::ChatMessage;
+use crate::chat::{ChatMessage, Provider, AUTH_ERROR_SENTINEL, DONE_SENTINEL, ERROR_SENTINEL};
+use crate::config::{self, Config, UserDirs};
+use crate::conversations::{self, Conversation};
+use crate::editor;
+use crate::history;
+use crate::keymap::KeyMap;
+use crate::preview::PreviewCache;
 use crate::rag::{RagChunk, RagIndex};
-use crate::search::{build_context, load_md_files, LoadedFile, SearchEntry, Searcher};
-use ignore::WalkBuilder;
+use crate::search::{build_context, file_context, load_md_files, EntryMode, LoadedFile, SearchEntry, Searcher};
+use crate::session::{self, Session};
+use crate::theme::Theme;
+use crate::util::unix_mtime;
+use crate::watch::{Changes, Watcher};
 use nucleo_matcher::{pattern::{CaseMatching, Normalization, Pattern}, Matcher, Utf32Str};
+use ratatui::text::Text;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
 use std::path::PathBuf;
 
+const PREVIEW_HSCROLL_STEP: usize = 8;
+/// Rows one mouse wheel notch scrolls text panes by.
+pub const WHEEL_STEP: usize = 3;
+/// Typing pause after which the query runs; faster keystrokes coalesce.
+const SEARCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(100);
+const CITATION_REPARSE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+/// Most entries the directory preview walks to count indexable files.
+const DIR_PREVIEW_WALK_LIMIT: usize = 2000;
+const IN_MEMORY_INDEX: &str = "Cache directory unavailable, search index kept in memory only";
+
+/// What the directory picker shows for a directory before switching to it.
+pub struct DirPreview {
+    /// Directories first, then files, each sorted by name.
+    pub entries: Vec<DirPreviewEntry>,
+    /// Files finder would index, counted up to `DIR_PREVIEW_WALK_LIMIT`
+    /// walked entries.
+    pub indexable: usize,
+    pub capped: bool,
+    /// Most recently modified indexable file, relative to the directory.
+    pub newest: Option<(String, u64)>,
+}
+
+pub struct DirPreviewEntry {
+    pub name: String,
+    /// Size in bytes, `None` for directories.
+    pub size: Option<u64>,
+    /// Unix seconds.
+    pub modified: Option<u64>,
+    /// A file finder would index.
+    pub indexed: bool,
+}
+
+impl DirPreview {
+    fn read(dir: &std::path::Path) -> Self {
+        let extensions = config::extensions(dir);
+        let has_extension = |path: &std::path::Path| {
+            path.extension()
+                .is_some_and(|ext| extensions.contains(&ext.to_string_lossy().to_lowercase()))
+        };
+
+        let mut entries: Vec<DirPreviewEntry> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| {
+                let meta = entry.metadata().ok();
+                let size = meta.as_ref().filter(|m| !m.is_dir()).map(|m| m.len());
+                DirPreviewEntry {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    size,
+                    modified: meta.as_ref().and_then(unix_mtime),
+                    indexed: size.is_some() && has_extension(&entry.path()),
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| (a.size.is_some(), &a.name).cmp(&(b.size.is_some(), &b.name)));
+
+        let mut indexable = 0;
+        let mut newest: Option<(String, u64)> = None;
+        let mut capped = false;
+        for (count, entry) in crate::search::walker(dir).build().flatten().enumerate() {
+            if count == DIR_PREVIEW_WALK_LIMIT {
+                capped = true;
+                break;
+            }
+            let path = entry.path();
+            if !has_extension(path) || !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            indexable += 1;
+            let Some(modified) = entry.metadata().ok().as_ref().and_then(unix_mtime) else { continue };
+            if newest.as_ref().is_none_or(|(_, newest)| modified > *newest) {
+                let name = path.strip_prefix(dir).unwrap_or(path).to_string_lossy().to_string();
+                newest = Some((name, modified));
+            }
+        }
+
+        Self { entries, indexable, capped, newest }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
     Search,
@@ -12,12 +107,149 @@ pub enum Mode {
     Citations,
     DirectoryPicker,
     QuickAnswer,
+    Compare,
+    Conversations,
+    Help,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name.trim().to_lowercase().as_str() {
+            "search" => Some(Mode::Search),
+            "chat" => Some(Mode::Chat),
+            "quick" => Some(Mode::QuickAnswer),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Citation {
     pub file: String,
     pub line: usize,
+    /// Last cited line; equal to `line` unless a range like `[f:20-25]` was cited.
+    pub end: usize,
+    pub column: Option<usize>,
+    pub valid: bool,
+    /// The cited line, trimmed; empty when it does not exist.
+    pub text: String,
+}
+
+/// The rendered chat and its links, with the revision and width they were
+/// rendered for.
+pub struct ChatRender {
+    pub revision: u64,
+    pub width: usize,
+    pub text: Text<'static>,
+    pub links: Vec<ChatLink>,
+}
+
+/// Where a link in the chat was drawn: a wrapped row and the columns its
+/// text covers on it.
+#[derive(Debug, Clone)]
+pub struct ChatLink {
+    pub row: usize,
+    pub columns: Range<usize>,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultAction {
+    Open,
+    CopyPath,
+    CopyLocation,
+    CopyContent,
+    Ask,
+}
+
+impl ResultAction {
+    pub const ALL: [ResultAction; 5] = [
+        ResultAction::Open,
+        ResultAction::CopyPath,
+        ResultAction::CopyLocation,
+        ResultAction::CopyContent,
+        ResultAction::Ask,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ResultAction::Open => "Open",
+            ResultAction::CopyPath => "Copy path",
+            ResultAction::CopyLocation => "Copy file:line",
+            ResultAction::CopyContent => "Copy text",
+            ResultAction::Ask => "Ask about this",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextMenu {
+    pub result: usize,
+    pub selected: usize,
+    pub position: (u16, u16),
+}
+
+/// Chunks received from a response stream that have not been shown yet.
+#[derive(Default)]
+struct StreamBuffer {
+    chunks: VecDeque<String>,
+    revealed_at: Option<std::time::Instant>,
+}
+
+impl StreamBuffer {
+    fn push(&mut self, chunk: String) {
+        if self.chunks.is_empty() {
+            self.revealed_at = Some(std::time::Instant::now());
+        }
+        self.chunks.push_back(chunk);
+    }
+
+    fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Coalesces buffered text into as few pieces as possible. With a
+    /// non-zero `chars_per_sec` only the text due since the last call is
+    /// released. Sentinels are passed through on their own, in order.
+    fn drain(&mut self, chars_per_sec: usize) -> Vec<String> {
+        let mut budget = match (chars_per_sec, self.revealed_at) {
+            (0, _) | (_, None) => usize::MAX,
+            (cps, Some(at)) => (at.elapsed().as_secs_f64() * cps as f64) as usize,
+        };
+        if budget == 0 {
+            return Vec::new();
+        }
+        self.revealed_at = Some(std::time::Instant::now());
+
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        while let Some(chunk) = self.chunks.front_mut() {
+            if chunk == DONE_SENTINEL || chunk.starts_with(ERROR_SENTINEL) || chunk.starts_with(AUTH_ERROR_SENTINEL) {
+                if !text.is_empty() {
+                    pieces.push(std::mem::take(&mut text));
+                }
+                pieces.extend(self.chunks.pop_front());
+                continue;
+            }
+            if budget == 0 {
+                break;
+            }
+            match chunk.char_indices().nth(budget) {
+                Some((split, _)) => {
+                    text.extend(chunk.drain(..split));
+                    budget = 0;
+                }
+                None => {
+                    budget -= chunk.chars().count();
+                    text.extend(self.chunks.pop_front());
+                }
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(text);
+        }
+        pieces
+    }
 }
 
 pub struct App {
@@ -26,101 +258,483 @@ pub struct App {
     pub selected: usize,
     pub should_quit: bool,
     pub selected_entry: Option<SearchEntry>,
+    pub selected_column: Option<usize>,
     pub cwd: PathBuf,
+    /// Directory finder was started in; the picker's last choice is
+    /// remembered per start directory.
+    start_dir: PathBuf,
+    from_archive: bool,
     pub entry_count: usize,
     pub mode: Mode,
+    /// Mode to return to when the help overlay closes.
+    pub previous_mode: Mode,
+    pub help_scroll: usize,
+    /// Furthest the help overlay can scroll, as of its last draw.
+    pub help_max_scroll: Cell<usize>,
     pub chat_input: String,
     pub chat_messages: Vec<ChatMessage>,
+    pub saved_conversations: Vec<Conversation>,
+    pub conversations_selected: usize,
     pub chat_response: String,
     pub chat_streaming: bool,
     pub chat_scroll: usize,
+    /// Bumped whenever the chat text changes, so the UI knows when its
+    /// rendered markdown is out of date.
+    pub chat_revision: u64,
+    pub chat_render: RefCell<Option<ChatRender>>,
+    /// Links on screen as of the last draw, in terminal cells.
+    pub chat_links_drawn: RefCell<Vec<ChatLink>>,
     pub md_context: String,
+    /// Send only the sections relevant to the question instead of every file.
+    pub chat_retrieval: bool,
+    pub provider: Provider,
     pub api_key: Option<String>,
+    pub model: String,
+    pub keymap: KeyMap,
+    pub theme: Theme,
     pub citations: Vec<Citation>,
     pub citations_query: String,
     pub citations_filtered: Vec<Citation>,
     pub citations_selected: usize,
+    pub citations_preview_offset: Option<usize>,
+    citations_parsed_at: std::time::Instant,
     searcher: Searcher,
     loaded_files: Vec<LoadedFile>,
-    rag_index: RagIndex,
+    rag_index: Option<RagIndex>,
+    rag_building: Option<std::sync::mpsc::Receiver<RagIndex>>,
+    rag_queued: bool,
+    watcher: Option<Watcher>,
+    pub previews: PreviewCache,
     pub dir_entries: Vec<PathBuf>,
     pub dir_filtered: Vec<PathBuf>,
     pub dir_query: String,
     pub dir_selected: usize,
-    pub original_cwd: PathBuf,
+    /// Subdirectories still being scanned, and whether the scan stopped at
+    /// `FINDER_DIR_LIMIT`.
+    dir_scanning: Option<std::sync::mpsc::Receiver<(Vec<PathBuf>, bool)>>,
+    pub dir_truncated: bool,
+    /// Previews of the directories selected since the picker opened.
+    pub dir_previews: HashMap<PathBuf, DirPreview>,
+    /// Previews being read on background threads.
+    dir_previews_pending: HashSet<PathBuf>,
+    dir_preview_tx: std::sync::mpsc::Sender<(PathBuf, DirPreview)>,
+    dir_preview_rx: std::sync::mpsc::Receiver<(PathBuf, DirPreview)>,
     pub quick_query: String,
     pub quick_response: String,
     pub quick_streaming: bool,
     pub quick_sources: Vec<RagChunk>,
     pub quick_sources_expanded: bool,
     pub quick_sources_selected: usize,
+    pub quick_sources_relaxed: bool,
     pub status_message: Option<(String, std::time::Instant)>,
     pub quick_history: Vec<(String, String)>,
     quick_pending_query: String,
+    pub config: Config,
+    /// Where history, sessions, conversations and the index cache live.
+    pub dirs: UserDirs,
+    pub context_menu: Option<ContextMenu>,
+    pub extension_filter: Option<String>,
+    pub file_scope: Option<String>,
+    pub wrap_lines: bool,
+    /// Show the lines around each result in the list instead of a preview pane.
+    pub inline_context: bool,
+    pub compare_files: Option<(String, String)>,
+    pub compare_scroll: usize,
+    compare_pending: Option<String>,
+    pub regex_mode: bool,
+    pub regex_error: bool,
+    search_pending: Option<std::time::Instant>,
+    search_history: Vec<String>,
+    history_pos: Option<usize>,
+    pub show_prompt: bool,
+    pub prompt_scroll: usize,
+    pub preview_hscroll: usize,
+    /// First preview line after the wheel scrolled it, with the result it
+    /// was scrolled for; a different selection centers its line again.
+    pub preview_offset: Option<(usize, usize)>,
+    /// First preview line as of the last draw.
+    pub preview_start_drawn: Cell<usize>,
+    pub result_expanded: bool,
+    pub confirm_rebuild_pending: bool,
+    last_autosave: std::time::Instant,
+    autosaved_len: (usize, usize),
+    chat_buffer: StreamBuffer,
+    quick_buffer: StreamBuffer,
 }
 
 impl App {
     pub fn new(cwd: PathBuf) -> Self {
-        let loaded_files = load_md_files(&cwd);
-        let searcher = Searcher::from_files(&loaded_files);
+        Self::with_dirs(cwd, UserDirs::detect())
+    }
+
+    /// Like `new`, keeping history, sessions, conversations and the index
+    /// cache under `dirs` instead of the user's own directories.
+    pub fn with_dirs(cwd: PathBuf, dirs: UserDirs) -> Self {
+        let loaded_files = load_md_files(&cwd, &config::extensions(&cwd));
+        Self::with_files(cwd, loaded_files, false, dirs)
+    }
+
+    /// Opens the directory last picked when finder was started in `cwd`,
+    /// falling back to `cwd` itself.
+    pub fn in_last_dir(cwd: PathBuf) -> Self {
+        let dirs = UserDirs::detect();
+        let Some(last) = session::load_last_dir(&dirs, &cwd) else {
+            return Self::with_dirs(cwd, dirs);
+        };
+        let loaded_files = load_md_files(&last, &config::extensions(&last));
+        let mut app = Self::with_files(last, loaded_files, false, dirs);
+        app.start_dir = cwd;
+        app
+    }
+
+    /// Builds the app over files read from an archive. `cwd` is the
+    /// directory they were extracted to.
+    #[cfg(feature = "archive")]
+    pub fn from_archive(cwd: PathBuf, loaded_files: Vec<LoadedFile>) -> Self {
+        Self::with_files(cwd, loaded_files, true, UserDirs::detect())
+    }
+
+    fn with_files(cwd: PathBuf, loaded_files: Vec<LoadedFile>, from_archive: bool, dirs: UserDirs) -> Self {
+        let config = Config::from_env();
+        let searcher = Searcher::from_files(&loaded_files, config.entry_mode, config.preserve_indent);
         let entry_count = searcher.entry_count();
         let md_context = build_context(&loaded_files);
-        let rag_index = RagIndex::new(&loaded_files, &cwd);
-        let api_key = crate::chat::find_api_key();
-        let original_cwd = cwd.clone();
+        let (provider, provider_warning) = Provider::from_env();
+        let api_key = crate::chat::find_api_key(provider, &dirs);
+        let model = config::model(&cwd, provider);
+        let (keymap, keymap_warnings) = KeyMap::new(&config::keybindings(&cwd));
+        let (dir_preview_tx, dir_preview_rx) = std::sync::mpsc::channel();
+        let (theme, theme_warnings) = config::theme(&cwd);
 
-        Self {
+        let mut app = Self {
             query: String::new(),
             results: Vec::new(),
             selected: 0,
             should_quit: false,
             selected_entry: None,
+            selected_column: None,
+            start_dir: cwd.clone(),
             cwd,
+            from_archive,
             entry_count,
-            mode: Mode::Search,
+            mode: config.default_mode,
+            previous_mode: config.default_mode,
+            help_scroll: 0,
+            help_max_scroll: Cell::new(0),
+            wrap_lines: config.wrap_lines,
+            inline_context: config.inline_context,
+            compare_files: None,
+            compare_scroll: 0,
+            compare_pending: None,
             chat_input: String::new(),
             chat_messages: Vec::new(),
+            saved_conversations: Vec::new(),
+            conversations_selected: 0,
             chat_response: String::new(),
             chat_streaming: false,
             chat_scroll: 0,
+            chat_revision: 0,
+            chat_render: RefCell::new(None),
+            chat_links_drawn: RefCell::new(Vec::new()),
             md_context,
+            chat_retrieval: config.chat_retrieval,
+            provider,
             api_key,
+            model,
+            keymap,
+            theme,
             citations: Vec::new(),
             citations_query: String::new(),
             citations_filtered: Vec::new(),
             citations_selected: 0,
+            citations_preview_offset: None,
+            citations_parsed_at: std::time::Instant::now(),
             searcher,
             loaded_files,
-            rag_index,
+            rag_index: None,
+            rag_building: None,
+            rag_queued: false,
+            watcher: None,
+            previews: PreviewCache::new(),
             dir_entries: Vec::new(),
             dir_filtered: Vec::new(),
             dir_query: String::new(),
             dir_selected: 0,
-            original_cwd,
+            dir_scanning: None,
+            dir_truncated: false,
+            dir_previews: HashMap::new(),
+            dir_previews_pending: HashSet::new(),
+            dir_preview_tx,
+            dir_preview_rx,
             quick_query: String::new(),
             quick_response: String::new(),
             quick_streaming: false,
             quick_sources: Vec::new(),
             quick_sources_expanded: false,
             quick_sources_selected: 0,
+            quick_sources_relaxed: false,
             status_message: None,
             quick_history: Vec::new(),
             quick_pending_query: String::new(),
+            config,
+            context_menu: None,
+            extension_filter: None,
+            file_scope: None,
+            regex_mode: false,
+            search_pending: None,
+            regex_error: false,
+            search_history: history::load(&dirs),
+            history_pos: None,
+            show_prompt: false,
+            prompt_scroll: 0,
+            preview_hscroll: 0,
+            preview_offset: None,
+            preview_start_drawn: Cell::new(0),
+            result_expanded: false,
+            confirm_rebuild_pending: false,
+            last_autosave: std::time::Instant::now(),
+            autosaved_len: (0, 0),
+            chat_buffer: StreamBuffer::default(),
+            quick_buffer: StreamBuffer::default(),
+            dirs,
+        };
+
+        app.start_rag_index();
+        app.start_watcher();
+
+        if !from_archive {
+            if let Some(last) = session::load_last_dir(&app.dirs, &app.cwd).filter(|d| *d != app.cwd) {
+                app.status_message = Some((
+                    format!("Last directory was {}. Run `f --last-dir` to reopen it", last.display()),
+                    std::time::Instant::now(),
+                ));
+            }
+        }
+
+        let mut warnings: Vec<String> = provider_warning.into_iter().collect();
+        if !keymap_warnings.is_empty() {
+            warnings.push(format!("{} in .finder.toml", keymap_warnings.join(", ")));
+        }
+        warnings.extend(theme_warnings);
+        if !warnings.is_empty() {
+            app.status_message = Some((warnings.join("; "), std::time::Instant::now()));
+        }
+
+        if app.config.autosave_secs > 0 {
+            if let Some(saved) = session::load(&app.dirs, &app.cwd) {
+                app.chat_messages = saved.chat_messages;
+                app.quick_history = saved.quick_history;
+                app.autosaved_len = (app.chat_messages.len(), app.quick_history.len());
+            }
+        }
+
+        app
+    }
+
+    /// True when an indexed file has since been removed from disk.
+    pub fn is_stale(&self, file: &str) -> bool {
+        !self.from_archive && !self.cwd.join(file).exists()
+    }
+
+    /// Reloads files from disk and rebuilds every index, keeping the query.
+    pub fn refresh_index(&mut self) {
+        if self.from_archive {
+            return;
+        }
+        self.loaded_files = load_md_files(&self.cwd, &config::extensions(&self.cwd));
+        self.rebuild_index();
+        self.status_message = Some((
+            format!("Index refreshed ({} files)", self.loaded_files.len()),
+            std::time::Instant::now(),
+        ));
+    }
+
+    /// Rebuilds the search index and chat context from `loaded_files` and
+    /// re-runs the query.
+    fn rebuild_index(&mut self) {
+        self.searcher = Searcher::from_files(&self.loaded_files, self.config.entry_mode, self.config.preserve_indent);
+        self.entry_count = self.searcher.entry_count();
+        self.md_context = build_context(&self.loaded_files);
+        self.start_rag_index();
+        self.update_search();
+    }
+
+    /// Re-reads only `paths`, which were created, edited or removed on disk;
+    /// a directory stands for every file under it. Returns false when none
+    /// of them is or was an indexed file.
+    fn reload_paths(&mut self, paths: &[PathBuf]) -> bool {
+        let extensions = config::extensions(&self.cwd);
+        let before = self.loaded_files.len();
+        let mut changed = false;
+        for path in paths {
+            let Ok(rel) = path.strip_prefix(&self.cwd) else { continue };
+            let name = rel.to_string_lossy().to_string();
+            let under = format!("{}{}", name, std::path::MAIN_SEPARATOR);
+            self.loaded_files.retain(|f| f.name != name && !f.name.starts_with(&under));
+
+            let tracked = if path.is_dir() {
+                crate::search::tracked_paths(path, &extensions)
+            } else if crate::search::is_tracked(path, &extensions) {
+                vec![path.clone()]
+            } else {
+                Vec::new()
+            };
+            changed |= !tracked.is_empty();
+            self.loaded_files.extend(crate::search::load_files(&self.cwd, tracked));
+        }
+        changed || self.loaded_files.len() != before
+    }
+
+    /// Called when the editor exits in edit-loop mode: re-indexes the edited
+    /// files and re-runs the query with the opened result still selected.
+    pub fn reload_after_edit(&mut self, opened: &SearchEntry) {
+        self.should_quit = false;
+        self.refresh_keeping_selection(opened);
+        self.status_message = Some((format!("Reloaded {}", opened.file), std::time::Instant::now()));
+    }
+
+    fn refresh_keeping_selection(&mut self, entry: &SearchEntry) {
+        let previous = self.selected;
+        self.refresh_index();
+        self.keep_selection(entry, previous);
+    }
+
+    fn keep_selection(&mut self, entry: &SearchEntry, previous: usize) {
+        self.selected = self
+            .results
+            .iter()
+            .position(|r| r.file == entry.file && r.line_num == entry.line_num)
+            .unwrap_or_else(|| previous.min(self.results.len().saturating_sub(1)));
+    }
+
+    /// Watches the directory for edits when `FINDER_WATCH` is on.
+    fn start_watcher(&mut self) {
+        self.watcher = (self.config.watch && !self.from_archive)
+            .then(|| Watcher::spawn(&self.cwd, config::extensions(&self.cwd)))
+            .flatten();
+    }
+
+    /// Re-reads the files that changed on disk and re-indexes, keeping the
+    /// query and selection.
+    pub fn poll_watcher(&mut self) {
+        let Some(changes) = self.watcher.as_ref().and_then(Watcher::changed) else { return };
+        match changes {
+            Changes::Paths(paths) => {
+                if !self.reload_paths(&paths) {
+                    return;
+                }
+            }
+            Changes::Unknown => self.loaded_files = load_md_files(&self.cwd, &config::extensions(&self.cwd)),
+        }
+        let (previous, entry) = (self.selected, self.results.get(self.selected).cloned());
+        self.rebuild_index();
+        if let Some(entry) = entry {
+            self.keep_selection(&entry, previous);
+        }
+        self.status_message = Some(("Files changed, index updated".to_string(), std::time::Instant::now()));
+    }
+
+    /// Loads the files the current mode previews so drawing never reads
+    /// from disk.
+    pub fn poll_previews(&mut self) {
+        let files: Vec<String> = match self.mode {
+            Mode::Search if !self.inline_context => {
+                self.results.get(self.selected).map(|e| e.file.clone()).into_iter().collect()
+            }
+            Mode::Citations => {
+                let citations = if self.citations_query.is_empty() {
+                    &self.citations
+                } else {
+                    &self.citations_filtered
+                };
+                citations.get(self.citations_selected).map(|c| c.file.clone()).into_iter().collect()
+            }
+            Mode::Compare => self
+                .compare_files
+                .iter()
+                .flat_map(|(left, right)| [left.clone(), right.clone()])
+                .collect(),
+            _ => Vec::new(),
+        };
+        for file in files {
+            let path = self.resolve(&file);
+            self.previews.request(&path);
+        }
+        self.previews.poll();
+    }
+
+    pub fn resolve(&self, file: &str) -> PathBuf {
+        self.cwd.join(file)
+    }
+
+    /// The `FINDER_CONTEXT_LINES` lines before and after a result, `None`
+    /// past either end of the file.
+    pub fn line_context(&self, file: &str, line_num: usize) -> (Vec<Option<&str>>, Vec<Option<&str>>) {
+        let n = self.config.context_lines;
+        let before = (1..=n)
+            .rev()
+            .map(|d| line_num.checked_sub(d).and_then(|l| self.file_line(file, l)))
+            .collect();
+        let after = (1..=n).map(|d| self.file_line(file, line_num + d)).collect();
+        (before, after)
+    }
+
+    pub fn save_session(&mut self) {
+        if self.config.autosave_secs == 0 {
+            return;
+        }
+        let session = Session {
+            chat_messages: self.chat_messages.clone(),
+            quick_history: self.quick_history.clone(),
+        };
+        session::save(&self.dirs, &self.cwd, &session);
+        self.last_autosave = std::time::Instant::now();
+        self.autosaved_len = (self.chat_messages.len(), self.quick_history.len());
+    }
+
+    pub fn autosave_if_idle(&mut self) {
+        if self.config.autosave_secs == 0 || self.chat_streaming || self.quick_streaming {
+            return;
+        }
+        let interval = std::time::Duration::from_secs(self.config.autosave_secs as u64);
+        let current_len = (self.chat_messages.len(), self.quick_history.len());
+        if current_len != self.autosaved_len && self.last_autosave.elapsed() >= interval {
+            self.save_session();
         }
     }
 
     pub fn parse_citations(&mut self) {
         self.citations.clear();
-        let re = regex::Regex::new(r"\[([^\]]+):(\d+)\]").unwrap();
+        let re = regex::Regex::new(r"\[([^\]]+?):(\d+(?:\s*[-,]\s*\d+)*)(?::(\d+))?\]").unwrap();
+        let mut prev_end = 0;
         for cap in re.captures_iter(&self.chat_response) {
+            let whole = cap.get(0).unwrap();
+            let preceding = &self.chat_response[prev_end..whole.start()];
+            prev_end = whole.end();
+
             let file = cap.get(1).map(|m| m.as_str().to_string()).unwrap_or_default();
-            let line = cap.get(2).and_then(|m| m.as_str().parse().ok()).unwrap_or(1);
-            if !self.citations.iter().any(|c| c.file == file && c.line == line) {
-                self.citations.push(Citation { file, line });
+            let spans = parse_line_spans(cap.get(2).map_or("", |m| m.as_str()));
+            let explicit_column = cap.get(3).and_then(|m| m.as_str().parse().ok()).filter(|_| spans.len() == 1);
+
+            for (line, end) in spans {
+                if self.citations.iter().any(|c| c.file == file && c.line == line) {
+                    continue;
+                }
+
+                let line_text = self.file_line(&file, line);
+                let valid = line_text.is_some();
+                let column = explicit_column.or_else(|| line_text.and_then(|text| infer_citation_column(preceding, text)));
+                let text = line_text.unwrap_or_default().trim().to_string();
+                self.citations.push(Citation { file: file.clone(), line, end, column, valid, text });
             }
         }
     }
 
+    fn file_line(&self, file: &str, line: usize) -> Option<&str> {
+        let loaded = self.loaded_files.iter().find(|f| f.name == file)?;
+        loaded.content.lines().nth(line.checked_sub(1)?)
+    }
+
     pub fn jump_to_citation(&mut self, idx: usize) {
         let citations = if self.citations_query.is_empty() {
             &self.citations
@@ -128,22 +742,76 @@ impl App {
             &self.citations_filtered
         };
         if let Some(citation) = citations.get(idx) {
+            if !citation.valid || self.is_stale(&citation.file) {
+                self.status_message = Some((
+                    format!("{}:{} does not exist", citation.file, citation.line),
+                    std::time::Instant::now(),
+                ));
+                return;
+            }
+            let line_num = citation.line.saturating_sub(self.config.citation_context).max(1);
+            let content = self.file_line(&citation.file, line_num).unwrap_or_default().to_string();
+            self.selected_column = citation.column.filter(|_| line_num == citation.line);
             self.selected_entry = Some(SearchEntry {
                 file: citation.file.clone(),
-                line_num: citation.line,
-                content: String::new(),
+                line_num,
+                content,
                 match_indices: Vec::new(),
+                match_groups: Vec::new(),
+                rank: 0,
+                lang: None,
             });
             self.should_quit = true;
         }
     }
 
+    /// URL of the chat link drawn at terminal cell (`col`, `row`).
+    pub fn link_at(&self, col: u16, row: u16) -> Option<String> {
+        self.chat_links_drawn
+            .borrow()
+            .iter()
+            .find(|link| link.row == row as usize && link.columns.contains(&(col as usize)))
+            .map(|link| link.url.clone())
+    }
+
+    /// Relative links to indexed markdown files open in the editor; web and
+    /// mailto links go to the system opener. Anything else is refused.
+    pub fn open_link(&mut self, url: &str) {
+        if !editor::is_web_link(url) {
+            let path = url.split('#').next().unwrap_or(url).trim_start_matches("./");
+            if self.loaded_files.iter().any(|f| f.name == path) {
+                self.selected_column = None;
+                self.selected_entry = Some(SearchEntry {
+                    file: path.to_string(),
+                    line_num: 1,
+                    content: String::new(),
+                    match_indices: Vec::new(),
+                    match_groups: Vec::new(),
+                    rank: 0,
+                    lang: None,
+                });
+                self.should_quit = true;
+                return;
+            }
+        }
+
+        if !editor::is_web_link(url) {
+            self.status_message = Some((
+                format!("Not opening {}: only web, mailto and indexed file links open", url),
+                std::time::Instant::now(),
+            ));
+        } else if !editor::open_external(url) {
+            self.status_message = Some((format!("Could not open {}", url), std::time::Instant::now()));
+        }
+    }
+
     pub fn enter_citations_mode(&mut self) {
         if !self.citations.is_empty() {
             self.mode = Mode::Citations;
             self.citations_query.clear();
             self.citations_filtered.clear();
             self.citations_selected = 0;
+            self.citations_preview_offset = None;
         }
     }
 
@@ -151,17 +819,66 @@ impl App {
         if self.citations_query.is_empty() {
             self.citations_filtered.clear();
             self.citations_selected = 0;
+            self.citations_preview_offset = None;
             return;
         }
 
-        let query = self.citations_query.to_lowercase();
-        self.citations_filtered = self
+        let mut matcher = Matcher::new(nucleo_matcher::Config::DEFAULT);
+        let pattern = Pattern::parse(&self.citations_query, CaseMatching::Ignore, Normalization::Smart);
+
+        let mut scored: Vec<(u32, Citation)> = self
             .citations
             .iter()
-            .filter(|c| c.file.to_lowercase().contains(&query))
-            .cloned()
+            .filter_map(|c| {
+                let haystack = format!("{} {}", c.file, c.text);
+                let mut buf = Vec::new();
+                pattern.score(Utf32Str::new(&haystack, &mut buf), &mut matcher).map(|score| (score, c.clone()))
+            })
             .collect();
+
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        self.citations_filtered = scored.into_iter().map(|(_, c)| c).collect();
         self.citations_selected = 0;
+        self.citations_preview_offset = None;
+    }
+
+    pub fn citations_list(&self) -> &[Citation] {
+        if self.citations_query.is_empty() {
+            &self.citations
+        } else {
+            &self.citations_filtered
+        }
+    }
+
+    /// Scrolls the search preview away from the selected result's line.
+    pub fn scroll_preview(&mut self, delta: isize, visible: usize) {
+        let Some(entry) = self.results.get(self.selected) else { return };
+        let total = match self.previews.get(&self.resolve(&entry.file)) {
+            crate::preview::Preview::Ready(content) => content.lines().count(),
+            _ => return,
+        };
+
+        let current = self.preview_start_drawn.get();
+        // A line centered near the end may already show past `max_start`.
+        let max_start = total.saturating_sub(visible).max(current);
+        let next = (current as isize + delta).clamp(0, max_start as isize) as usize;
+        self.preview_offset = Some((self.selected, next));
+    }
+
+    pub fn scroll_citation_preview(&mut self, delta: isize, visible: usize) {
+        let Some(citation) = self.citations_list().get(self.citations_selected) else { return };
+        let total = self
+            .loaded_files
+            .iter()
+            .find(|f| f.name == citation.file)
+            .map_or(0, |f| f.content.lines().count());
+
+        let max_start = total.saturating_sub(visible);
+        let current = self
+            .citations_preview_offset
+            .unwrap_or_else(|| preview_window_start(citation.line, visible, total));
+        let next = (current as isize + delta).clamp(0, max_start as isize) as usize;
+        self.citations_preview_offset = Some(next);
     }
 
     pub fn citations_count(&self) -> usize {
@@ -172,6 +889,16 @@ impl App {
         }
     }
 
+    pub fn enter_chat(&mut self) {
+        self.mode = Mode::Chat;
+    }
+
+    pub fn enter_quick_answer(&mut self) {
+        self.mode = Mode::QuickAnswer;
+        self.quick_query.clear();
+        self.quick_response.clear();
+    }
+
     pub fn on_char(&mut self, c: char) {
         if c == '\n' || c == '\r' {
             return;
@@ -179,16 +906,8 @@ impl App {
 
         match self.mode {
             Mode::Search => {
-                if c == '?' {
-                    self.mode = Mode::Chat;
-                } else if c == '@' && self.query.is_empty() {
-                    self.mode = Mode::QuickAnswer;
-                    self.quick_query.clear();
-                    self.quick_response.clear();
-                } else {
-                    self.query.push(c);
-                    self.update_search();
-                }
+                self.query.push(c);
+                self.search_pending = Some(std::time::Instant::now());
             }
             Mode::Chat => {
                 if !self.chat_streaming {
@@ -208,6 +927,7 @@ impl App {
                     self.quick_query.push(c);
                 }
             }
+            Mode::Compare | Mode::Conversations | Mode::Help => {}
         }
     }
 
@@ -215,7 +935,7 @@ impl App {
         match self.mode {
             Mode::Search => {
                 self.query.pop();
-                self.update_search();
+                self.search_pending = Some(std::time::Instant::now());
             }
             Mode::Chat => {
                 if !self.chat_streaming {
@@ -239,14 +959,23 @@ impl App {
                     }
                 }
             }
+            Mode::Compare | Mode::Conversations | Mode::Help => {}
         }
     }
 
     pub fn on_up(&mut self) {
+        if self.show_prompt {
+            self.prompt_scroll = self.prompt_scroll.saturating_sub(1);
+            return;
+        }
         match self.mode {
             Mode::Search => {
-                if self.selected > 0 {
+                if self.results.is_empty() {
+                    self.history_prev();
+                } else if self.selected > 0 {
                     self.selected -= 1;
+                    self.preview_hscroll = 0;
+                    self.result_expanded = false;
                 }
             }
             Mode::Chat => {
@@ -257,6 +986,7 @@ impl App {
             Mode::Citations => {
                 if self.citations_selected > 0 {
                     self.citations_selected -= 1;
+                    self.citations_preview_offset = None;
                 }
             }
             Mode::DirectoryPicker => {
@@ -265,14 +995,25 @@ impl App {
                 }
             }
             Mode::QuickAnswer => {}
+            Mode::Compare => self.scroll_compare(-1),
+            Mode::Conversations => self.conversations_selected = self.conversations_selected.saturating_sub(1),
+            Mode::Help => self.help_scroll = self.help_scroll.saturating_sub(1),
         }
     }
 
     pub fn on_down(&mut self) {
+        if self.show_prompt {
+            self.prompt_scroll += 1;
+            return;
+        }
         match self.mode {
             Mode::Search => {
-                if self.selected + 1 < self.results.len() {
+                if self.results.is_empty() {
+                    self.history_next();
+                } else if self.selected + 1 < self.results.len() {
                     self.selected += 1;
+                    self.preview_hscroll = 0;
+                    self.result_expanded = false;
                 }
             }
             Mode::Chat => {
@@ -282,6 +1023,7 @@ impl App {
                 let count = self.citations_count();
                 if self.citations_selected + 1 < count {
                     self.citations_selected += 1;
+                    self.citations_preview_offset = None;
                 }
             }
             Mode::DirectoryPicker => {
@@ -291,14 +1033,163 @@ impl App {
                 }
             }
             Mode::QuickAnswer => {}
+            Mode::Compare => self.scroll_compare(1),
+            Mode::Conversations => {
+                if self.conversations_selected + 1 < self.saved_conversations.len() {
+                    self.conversations_selected += 1;
+                }
+            }
+            Mode::Help => self.help_scroll = (self.help_scroll + 1).min(self.help_max_scroll.get()),
+        }
+    }
+
+    /// Mouse wheel: scrolls text panes and moves the selection in lists.
+    pub fn on_scroll(&mut self, down: bool) {
+        match self.mode {
+            // Up/Down walk the search history when there are no results.
+            Mode::Search if self.results.is_empty() => {}
+            Mode::Chat if !self.show_prompt => {
+                self.chat_scroll = if down {
+                    self.chat_scroll + WHEEL_STEP
+                } else {
+                    self.chat_scroll.saturating_sub(WHEEL_STEP)
+                };
+            }
+            Mode::Compare => self.scroll_compare(if down { WHEEL_STEP as isize } else { -(WHEEL_STEP as isize) }),
+            Mode::QuickAnswer if self.quick_sources_expanded => {
+                if down {
+                    self.quick_sources_down();
+                } else {
+                    self.quick_sources_up();
+                }
+            }
+            _ if down => self.on_down(),
+            _ => self.on_up(),
         }
     }
 
     pub fn on_enter(&mut self) {
-        if let Some(entry) = self.results.get(self.selected) {
-            self.selected_entry = Some(entry.clone());
-            self.should_quit = true;
+        let Some(entry) = self.entry_to_open() else { return };
+        if self.config.open_in_split && editor::open_in_split(&self.resolve(&entry.file), entry.line_num, None) {
+            return;
+        }
+        self.selected_entry = Some(entry);
+        self.should_quit = true;
+    }
+
+    /// The selected result, about to be opened: records the query in the
+    /// history and sets `selected_column`. `None` when its file is gone.
+    pub fn entry_to_open(&mut self) -> Option<SearchEntry> {
+        let entry = self.results.get(self.selected)?.clone();
+        if self.is_stale(&entry.file) {
+            self.status_message = Some((
+                format!("{} no longer exists. Press F5 to refresh the index", entry.file),
+                std::time::Instant::now(),
+            ));
+            return None;
+        }
+        history::record(&self.dirs, &mut self.search_history, &self.query);
+        self.selected_column = self.match_column(&entry);
+        Some(entry)
+    }
+
+    pub fn open_context_menu(&mut self, result: usize, position: (u16, u16)) {
+        if result < self.results.len() {
+            self.context_menu = Some(ContextMenu { result, selected: 0, position });
+        }
+    }
+
+    pub fn close_context_menu(&mut self) {
+        self.context_menu = None;
+    }
+
+    pub fn context_menu_up(&mut self) {
+        if let Some(menu) = self.context_menu.as_mut() {
+            menu.selected = menu.selected.saturating_sub(1);
+        }
+    }
+
+    pub fn context_menu_down(&mut self) {
+        if let Some(menu) = self.context_menu.as_mut() {
+            if menu.selected + 1 < ResultAction::ALL.len() {
+                menu.selected += 1;
+            }
+        }
+    }
+
+    pub fn apply_context_action(&mut self) -> Option<String> {
+        let menu = self.context_menu.take()?;
+        let entry = self.results.get(menu.result)?.clone();
+
+        match ResultAction::ALL[menu.selected] {
+            ResultAction::Open => {
+                self.selected_entry = Some(entry);
+                self.should_quit = true;
+                None
+            }
+            ResultAction::CopyPath => Some(self.resolve(&entry.file).to_string_lossy().to_string()),
+            ResultAction::CopyLocation => Some(format!("{}:{}", entry.file, entry.line_num)),
+            ResultAction::CopyContent => self.result_content(menu.result),
+            ResultAction::Ask => {
+                self.mode = Mode::Chat;
+                self.chat_input = format!("About {}:{}: ", entry.file, entry.line_num);
+                None
+            }
+        }
+    }
+
+    pub fn toggle_result_expanded(&mut self) {
+        self.result_expanded = !self.result_expanded && !self.results.is_empty();
+    }
+
+    pub fn preview_left(&mut self) {
+        self.preview_hscroll = self.preview_hscroll.saturating_sub(PREVIEW_HSCROLL_STEP);
+    }
+
+    pub fn preview_right(&mut self) {
+        let Some(entry) = self.results.get(self.selected) else { return };
+        let Ok(content) = std::fs::read_to_string(self.resolve(&entry.file)) else { return };
+        let longest = content.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+        self.preview_hscroll = (self.preview_hscroll + PREVIEW_HSCROLL_STEP).min(longest.saturating_sub(1));
+    }
+
+    /// Byte column (1-based) of the first match on the original file line,
+    /// accounting for indentation stripped from the entry's content.
+    fn match_column(&self, entry: &SearchEntry) -> Option<usize> {
+        if self.config.entry_mode != EntryMode::Line {
+            return None;
         }
+        let first = *entry.match_indices.first()? as usize;
+        let line = self.file_line(&entry.file, entry.line_num)?;
+        let indent = line.len() - line.trim_start().len();
+        let offset = if entry.content.starts_with(char::is_whitespace) { 0 } else { indent };
+        let byte = entry.content.char_indices().nth(first).map(|(b, _)| b)?;
+        Some(offset + byte + 1)
+    }
+
+    pub fn result_content(&self, idx: usize) -> Option<String> {
+        let entry = self.results.get(idx)?;
+        if self.config.copy_trimmed || self.config.entry_mode != EntryMode::Line {
+            return Some(entry.content.trim_start().to_string());
+        }
+        self.file_line(&entry.file, entry.line_num)
+            .map(str::to_string)
+            .or_else(|| Some(entry.content.clone()))
+    }
+
+    /// `file:line` of the selected result.
+    pub fn result_location(&self) -> Option<String> {
+        let entry = self.results.get(self.selected)?;
+        Some(format!("{}:{}", entry.file, entry.line_num))
+    }
+
+    pub fn preview_snippet(&self) -> Option<String> {
+        let entry = self.results.get(self.selected)?;
+        let content = std::fs::read_to_string(self.resolve(&entry.file)).ok()?;
+        let lines: Vec<&str> = content.lines().collect();
+        let target = entry.line_num.checked_sub(1).filter(|&t| t < lines.len())?;
+        let (start, end) = snippet_bounds(&lines, target);
+        Some(dedent(&lines[start..end]))
     }
 
     pub fn on_escape(&mut self) {
@@ -308,6 +1199,7 @@ impl App {
                 if self.chat_streaming {
                     return;
                 }
+                self.show_prompt = false;
                 self.mode = Mode::Search;
                 self.chat_input.clear();
             }
@@ -316,6 +1208,7 @@ impl App {
                 self.citations_query.clear();
                 self.citations_filtered.clear();
                 self.citations_selected = 0;
+                self.citations_preview_offset = None;
             }
             Mode::DirectoryPicker => {
                 self.mode = Mode::Search;
@@ -327,14 +1220,68 @@ impl App {
                 if self.quick_streaming {
                     return;
                 }
+                self.show_prompt = false;
                 self.mode = Mode::Search;
                 self.quick_query.clear();
                 self.quick_response.clear();
                 self.quick_history.clear();
             }
+            Mode::Compare => {
+                self.mode = Mode::Search;
+                self.compare_files = None;
+            }
+            Mode::Conversations => {
+                self.mode = Mode::Chat;
+                self.saved_conversations.clear();
+            }
+            Mode::Help => self.mode = self.previous_mode,
         }
     }
 
+    /// Opens the key binding overlay over the current mode, or closes it.
+    pub fn toggle_help(&mut self) {
+        if self.mode == Mode::Help {
+            self.mode = self.previous_mode;
+        } else {
+            self.previous_mode = self.mode;
+            self.mode = Mode::Help;
+            self.help_scroll = 0;
+        }
+    }
+
+    /// Marks the selected result's file for comparison; the second distinct
+    /// file marked opens both side by side.
+    pub fn mark_for_compare(&mut self) {
+        let Some(file) = self.results.get(self.selected).map(|e| e.file.clone()) else { return };
+
+        match self.compare_pending.take() {
+            Some(first) if first != file => {
+                self.compare_files = Some((first, file));
+                self.compare_scroll = 0;
+                self.mode = Mode::Compare;
+            }
+            _ => {
+                self.status_message = Some((
+                    format!("Marked {}. Press Ctrl+D on another file to compare", file),
+                    std::time::Instant::now(),
+                ));
+                self.compare_pending = Some(file);
+            }
+        }
+    }
+
+    pub fn scroll_compare(&mut self, delta: isize) {
+        let Some((left, right)) = &self.compare_files else { return };
+        // The panes draw what the preview cache holds, which may be newer
+        // than the indexed copy.
+        let line_count = |name: &str| match self.previews.get(&self.resolve(name)) {
+            crate::preview::Preview::Ready(content) => content.lines().count(),
+            _ => 0,
+        };
+        let max = line_count(left).max(line_count(right)).saturating_sub(1);
+        self.compare_scroll = self.compare_scroll.saturating_add_signed(delta).min(max);
+    }
+
     pub fn start_chat(&mut self) {
         if self.chat_input.is_empty() || self.chat_streaming || self.api_key.is_none() {
             return;
@@ -345,33 +1292,231 @@ impl App {
             content: self.chat_input.clone(),
         });
 
-        self.chat_input.clear();
+        if !self.config.keep_chat_input {
+            self.chat_input.clear();
+        }
         self.chat_response.clear();
+        self.citations.clear();
         self.chat_streaming = true;
         self.chat_scroll = 0;
+        self.chat_revision += 1;
+    }
+
+    pub fn toggle_chat_retrieval(&mut self) {
+        self.chat_retrieval = !self.chat_retrieval;
+        let message = if self.chat_retrieval {
+            "Chat sends the most relevant sections"
+        } else {
+            "Chat sends every document"
+        };
+        self.status_message = Some((message.to_string(), std::time::Instant::now()));
+    }
+
+    /// Looks for the API key again, e.g. after `~/.env` was created.
+    pub fn reload_api_key(&mut self) {
+        self.api_key = crate::chat::find_api_key(self.provider, &self.dirs);
+        let Some(var) = self.provider.key_var() else {
+            let message = format!("{} needs no API key", self.provider.label());
+            self.status_message = Some((message, std::time::Instant::now()));
+            return;
+        };
+        let message = if self.api_key.is_some() {
+            format!("{} loaded", var)
+        } else {
+            format!("{} still not found", var)
+        };
+        self.status_message = Some((message, std::time::Instant::now()));
+    }
+
+    pub fn save_conversation(&mut self) {
+        let message = if self.chat_messages.is_empty() {
+            "Nothing to save yet".to_string()
+        } else {
+            match conversations::save(&self.dirs, &self.chat_messages) {
+                Ok(path) => format!("Saved to {}", path.display()),
+                Err(e) => format!("Could not save the conversation: {}", e),
+            }
+        };
+        self.status_message = Some((message, std::time::Instant::now()));
+    }
+
+    pub fn enter_conversations(&mut self) {
+        self.saved_conversations = conversations::list(&self.dirs);
+        if self.saved_conversations.is_empty() {
+            self.status_message = Some(("No saved conversations".to_string(), std::time::Instant::now()));
+            return;
+        }
+        self.conversations_selected = 0;
+        self.mode = Mode::Conversations;
+    }
+
+    /// Replaces the current chat with the selected saved conversation.
+    pub fn open_conversation(&mut self) {
+        if self.conversations_selected < self.saved_conversations.len() {
+            let conversation = self.saved_conversations.swap_remove(self.conversations_selected);
+            self.chat_messages = conversation.messages;
+            self.chat_response.clear();
+            self.citations.clear();
+            self.chat_scroll = 0;
+            self.chat_revision += 1;
+        }
+        self.saved_conversations.clear();
+        self.mode = Mode::Chat;
+    }
+
+    /// Scrolls the chat response to the next (or previous) heading row.
+    pub fn jump_to_heading(&mut self, heading_rows: &[usize], forward: bool) {
+        let target = if forward {
+            heading_rows.iter().find(|&&row| row > self.chat_scroll)
+        } else {
+            heading_rows.iter().rev().find(|&&row| row < self.chat_scroll)
+        };
+        if let Some(&row) = target {
+            self.chat_scroll = row;
+        }
+    }
+
+    /// Puts the last question sent back into the chat input for editing.
+    pub fn recall_last_question(&mut self) {
+        if let Some(last) = self.chat_messages.iter().rev().find(|m| m.role == "user") {
+            self.chat_input = last.content.clone();
+        }
     }
 
     pub fn append_response(&mut self, text: &str) {
-        if text == "\n[DONE]" {
+        self.chat_revision += 1;
+        if let Some(err) = crate::chat::error_message(self.provider, text) {
             self.chat_streaming = false;
-            self.parse_citations();
+            self.chat_response.push_str(&format!("\n\n[error: {}]", err));
+        } else if text == DONE_SENTINEL {
+            self.chat_streaming = false;
+            if self.config.citations {
+                self.parse_citations();
+            }
             self.chat_messages.push(ChatMessage {
                 role: "assistant".to_string(),
                 content: self.chat_response.clone(),
             });
         } else {
             self.chat_response.push_str(text);
+            if self.config.citations && self.citations_parsed_at.elapsed() >= CITATION_REPARSE_INTERVAL {
+                self.parse_citations();
+                self.citations_parsed_at = std::time::Instant::now();
+            }
+        }
+    }
+
+    pub fn queue_response(&mut self, chunk: String) {
+        self.chat_buffer.push(chunk);
+    }
+
+    pub fn queue_quick_response(&mut self, chunk: String) {
+        self.quick_buffer.push(chunk);
+    }
+
+    /// Applies buffered stream chunks once per frame, paced by
+    /// `FINDER_TYPEWRITER_CPS` when set.
+    pub fn flush_streams(&mut self) {
+        let cps = self.config.typewriter_cps;
+        for piece in self.chat_buffer.drain(cps) {
+            self.append_response(&piece);
+        }
+        for piece in self.quick_buffer.drain(cps) {
+            self.append_quick_response(&piece);
         }
     }
 
     pub fn cancel_streaming(&mut self) {
+        self.chat_buffer.clear();
         if self.chat_streaming {
             self.chat_streaming = false;
             self.chat_response.push_str("\n\n[cancelled]");
+            self.chat_revision += 1;
+        }
+    }
+
+    /// The question about to be sent followed by the earlier ones, newest first.
+    fn recent_questions(&self) -> impl Iterator<Item = &str> {
+        let earlier = self.chat_messages.iter().rev().filter(|m| m.role == "user").map(|m| m.content.as_str());
+        std::iter::once(self.chat_input.as_str()).filter(|q| !q.is_empty()).chain(earlier)
+    }
+
+    /// Sections the quick-answer index finds for the last two questions,
+    /// for retrieval-augmented chat. `None` while the index is not ready or
+    /// when nothing matched.
+    fn retrieved_documents(&self) -> Option<String> {
+        let index = self.rag_index.as_ref()?;
+        let query = self.recent_questions().take(2).collect::<Vec<_>>().join(" ");
+        let (chunks, _) = index.search_chunks_relaxed(&query, self.config.source_limit, self.config.source_threshold);
+        if chunks.is_empty() {
+            return None;
+        }
+        Some(
+            chunks
+                .iter()
+                .take(self.config.prompt_source_limit)
+                .map(|c| format!("[{}:{}] {}\n\n", c.file, c.line, c.content))
+                .collect(),
+        )
+    }
+
+    /// `md_context`, unless it is over `FINDER_CONTEXT_BUDGET` characters.
+    /// Then whole files are picked until the budget is spent, the ones the
+    /// quick-answer index finds most relevant to the latest question first.
+    /// With retrieval on, only the relevant sections are sent.
+    fn chat_documents(&self) -> Cow<'_, str> {
+        if self.chat_retrieval {
+            if let Some(documents) = self.retrieved_documents() {
+                return Cow::Owned(documents);
+            }
+        }
+
+        let budget = self.config.context_budget;
+        if budget == 0 || self.md_context.chars().count() <= budget {
+            return Cow::Borrowed(&self.md_context);
         }
+
+        let question = self.recent_questions().next().unwrap_or("");
+        let mut ranked: Vec<&LoadedFile> = Vec::new();
+        if let Some(index) = self.rag_index.as_ref().filter(|_| !question.trim().is_empty()) {
+            for chunk in index.search_chunks_relaxed(question, 100, 0.0).0 {
+                let Some(file) = self.loaded_files.iter().find(|f| f.name == chunk.file) else { continue };
+                if !ranked.iter().any(|r| r.name == file.name) {
+                    ranked.push(file);
+                }
+            }
+        }
+        let rest = self.loaded_files.iter().filter(|f| !ranked.iter().any(|r| r.name == f.name));
+        let order = ranked.iter().copied().chain(rest);
+
+        let mut context = String::new();
+        let (mut used, mut included) = (0, 0);
+        for file in order {
+            let part = file_context(file);
+            let chars = part.chars().count();
+            if used + chars <= budget {
+                context.push_str(&part);
+                used += chars;
+                included += 1;
+            }
+        }
+        context.push_str(&format!(
+            "\n(Only {} of {} files fit in the context; ask about a specific file if it is missing.)\n",
+            included,
+            self.loaded_files.len()
+        ));
+        Cow::Owned(context)
     }
 
     pub fn build_messages(&self) -> Vec<ChatMessage> {
+        let citation_rules = if self.config.citations {
+            r#"3. When referencing the documents, include citations using [file:line] format
+4. Place citations inline: "The installation requires cargo [README.md:20]"
+"#
+        } else {
+            "3. Do not include [file:line] references in your answer\n"
+        };
+
         let mut messages = vec![ChatMessage {
             role: "system".to_string(),
             content: format!(
@@ -387,12 +1532,11 @@ FORMATTING RULES:
    - Use `code` for inline code, commands, or file names
    - Use code blocks with ``` for multi-line code
 2. Keep responses concise and well-structured
-3. When referencing the documents, include citations using [file:line] format
-4. Place citations inline: "The installation requires cargo [README.md:20]"
-
+{}
 DOCUMENTS:
 {}"#,
-                self.md_context
+                citation_rules,
+                self.chat_documents()
             ),
         }];
         messages.extend(self.chat_messages.clone());
@@ -405,30 +1549,151 @@ DOCUMENTS:
         messages
     }
 
+    pub fn system_prompt(&self) -> String {
+        let messages = if self.mode == Mode::QuickAnswer {
+            self.build_quick_messages()
+        } else {
+            self.build_messages()
+        };
+        messages.into_iter().next().map(|m| m.content).unwrap_or_default()
+    }
+
+    pub fn toggle_prompt_view(&mut self) {
+        self.show_prompt = !self.show_prompt;
+        self.prompt_scroll = 0;
+    }
+
+    pub fn history_prev(&mut self) {
+        let pos = match self.history_pos {
+            Some(pos) => pos.saturating_sub(1),
+            None => match self.search_history.len().checked_sub(1) {
+                Some(last) => last,
+                None => return,
+            },
+        };
+        self.query = self.search_history[pos].clone();
+        self.update_search();
+        self.history_pos = Some(pos);
+    }
+
+    pub fn history_next(&mut self) {
+        let Some(pos) = self.history_pos else { return };
+        if pos + 1 < self.search_history.len() {
+            self.query = self.search_history[pos + 1].clone();
+            self.update_search();
+            self.history_pos = Some(pos + 1);
+        } else {
+            self.query.clear();
+            self.update_search();
+        }
+    }
+
+    /// Runs the typed query once typing pauses for `SEARCH_DEBOUNCE`.
+    pub fn poll_search(&mut self) {
+        if self.search_pending.is_some_and(|t| t.elapsed() >= SEARCH_DEBOUNCE) {
+            self.update_search();
+        }
+    }
+
+    /// Runs a pending query right away, before acting on the results.
+    pub fn flush_search(&mut self) {
+        if self.search_pending.is_some() {
+            self.update_search();
+        }
+    }
+
     fn update_search(&mut self) {
+        self.search_pending = None;
+        self.history_pos = None;
         self.selected = 0;
+        self.preview_hscroll = 0;
+        self.preview_offset = None;
+        self.result_expanded = false;
+        self.context_menu = None;
 
         if self.query.is_empty() {
+            self.regex_error = false;
             self.results.clear();
+        } else if self.regex_mode {
+            // Keep the previous results while the pattern is mid-edit and invalid.
+            match regex::Regex::new(&self.query) {
+                Ok(re) => {
+                    self.regex_error = false;
+                    self.results = self.searcher.search_regex(&re, self.file_scope.as_deref());
+                }
+                Err(_) => {
+                    self.regex_error = true;
+                    return;
+                }
+            }
         } else {
-            self.results = self.searcher.search(&self.query);
+            self.results = self.searcher.search(&self.query, self.file_scope.as_deref());
         }
+
+        if let Some(ext) = &self.extension_filter {
+            self.results
+                .retain(|e| file_extension(&e.file).as_deref() == Some(ext.as_str()));
+        }
+    }
+
+    pub fn toggle_regex_mode(&mut self) {
+        self.regex_mode = !self.regex_mode;
+        self.regex_error = false;
+        self.update_search();
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        self.wrap_lines = !self.wrap_lines;
+        self.preview_hscroll = 0;
+        self.preview_offset = None;
+    }
+
+    pub fn toggle_inline_context(&mut self) {
+        self.inline_context = !self.inline_context;
+        self.preview_hscroll = 0;
+    }
+
+    pub fn toggle_file_scope(&mut self) {
+        if self.file_scope.take().is_none() {
+            self.file_scope = self.results.get(self.selected).map(|e| e.file.clone());
+        }
+        self.update_search();
+    }
+
+    pub fn toggle_extension_filter(&mut self) {
+        if self.extension_filter.take().is_none() {
+            self.extension_filter = self
+                .results
+                .get(self.selected)
+                .and_then(|e| file_extension(&e.file));
+        }
+        self.update_search();
     }
 
     pub fn enter_directory_picker(&mut self) {
-        self.dir_entries = self.scan_directories();
+        if self.from_archive {
+            self.status_message = Some((
+                "Directory picker is unavailable when reading an archive".to_string(),
+                std::time::Instant::now(),
+            ));
+            return;
+        }
+        self.dir_entries = self.parent_directories();
+        self.dir_truncated = false;
+        self.dir_previews.clear();
+        self.scan_directories();
         self.dir_filtered.clear();
         self.dir_query.clear();
         self.dir_selected = 0;
         self.mode = Mode::DirectoryPicker;
     }
 
-    fn scan_directories(&self) -> Vec<PathBuf> {
+    fn parent_directories(&self) -> Vec<PathBuf> {
         let mut dirs = Vec::new();
 
         // Add parent directories (up to 3 levels) as relative paths
         for i in 1..=3 {
-            let mut parent_path = self.original_cwd.clone();
+            let mut parent_path = self.cwd.clone();
             for _ in 0..i {
                 if let Some(p) = parent_path.parent() {
                     parent_path = p.to_path_buf();
@@ -436,36 +1701,64 @@ DOCUMENTS:
                     break;
                 }
             }
-            if parent_path != self.original_cwd {
+            if parent_path != self.cwd {
                 let prefix = "../".repeat(i);
                 dirs.push(PathBuf::from(prefix.trim_end_matches('/')));
             }
         }
 
-        // Add subdirectories (5 levels deep)
-        let walker = WalkBuilder::new(&self.original_cwd)
-            .hidden(true)
-            .git_ignore(true)
-            .max_depth(Some(5))
-            .build();
-
-        for result in walker {
-            let Ok(entry) = result else { continue };
-            let path = entry.path();
+        dirs
+    }
 
-            if !path.is_dir() {
-                continue;
-            }
+    /// Lists subdirectories on a background thread so the picker opens at
+    /// once in large trees; `poll_directories` adds them when done.
+    fn scan_directories(&mut self) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let cwd = self.cwd.clone();
+        let (depth, limit) = (self.config.dir_depth, self.config.dir_limit);
+        std::thread::spawn(move || {
+            let _ = tx.send(scan_subdirectories(&cwd, depth, limit));
+        });
+        self.dir_scanning = Some(rx);
+    }
 
-            if let Ok(rel) = path.strip_prefix(&self.original_cwd) {
-                if !rel.as_os_str().is_empty() {
-                    dirs.push(rel.to_path_buf());
+    pub fn poll_directories(&mut self) {
+        if self.mode == Mode::DirectoryPicker {
+            if let Some(dir) = self.dir_list().get(self.dir_selected) {
+                let path = self.cwd.join(dir);
+                let path = path.canonicalize().unwrap_or(path);
+                if !self.dir_previews.contains_key(&path) && self.dir_previews_pending.insert(path.clone()) {
+                    let tx = self.dir_preview_tx.clone();
+                    std::thread::spawn(move || {
+                        let preview = DirPreview::read(&path);
+                        let _ = tx.send((path, preview));
+                    });
                 }
             }
         }
+        for (path, preview) in self.dir_preview_rx.try_iter() {
+            self.dir_previews_pending.remove(&path);
+            self.dir_previews.insert(path, preview);
+        }
 
-        dirs.sort();
-        dirs
+        let Some(rx) = &self.dir_scanning else { return };
+        let (dirs, truncated) = match rx.try_recv() {
+            Ok(scan) => scan,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => (Vec::new(), false),
+        };
+        self.dir_scanning = None;
+        self.dir_entries.extend(dirs);
+        self.dir_truncated = truncated;
+        if !self.dir_query.is_empty() {
+            let selected = self.dir_selected;
+            self.filter_directories();
+            self.dir_selected = selected.min(self.dir_filtered.len().saturating_sub(1));
+        }
+    }
+
+    pub fn dir_scanning(&self) -> bool {
+        self.dir_scanning.is_some()
     }
 
     pub fn filter_directories(&mut self) {
@@ -489,7 +1782,7 @@ DOCUMENTS:
             })
             .collect();
 
-        scored.sort_by(|a, b| b.0.cmp(&a.0));
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
         self.dir_filtered = scored.into_iter().map(|(_, p)| p).collect();
         self.dir_selected = 0;
     }
@@ -505,23 +1798,59 @@ DOCUMENTS:
     pub fn select_directory(&mut self) {
         let list = self.dir_list();
         if let Some(selected) = list.get(self.dir_selected) {
-            let new_cwd = self.original_cwd.join(selected);
+            let new_cwd = self.cwd.join(selected);
             if let Ok(canonical) = new_cwd.canonicalize() {
-                self.cwd = canonical.clone();
-                self.original_cwd = canonical;
-                self.loaded_files = load_md_files(&self.cwd);
-                self.searcher = Searcher::from_files(&self.loaded_files);
+                self.save_session();
+                if !self.from_archive {
+                    session::save_last_dir(&self.dirs, &self.start_dir, &canonical);
+                }
+                self.cwd = canonical;
+                self.model = config::model(&self.cwd, self.provider);
+                self.loaded_files = load_md_files(&self.cwd, &config::extensions(&self.cwd));
+                self.searcher = Searcher::from_files(&self.loaded_files, self.config.entry_mode, self.config.preserve_indent);
                 self.entry_count = self.searcher.entry_count();
                 self.md_context = build_context(&self.loaded_files);
-                self.rag_index = RagIndex::new(&self.loaded_files, &self.cwd);
+                self.rag_building = None;
+                self.rag_queued = false;
+                self.start_rag_index();
+                self.start_watcher();
                 self.query.clear();
                 self.results.clear();
                 self.selected = 0;
+                // Citations and sources point at files of the previous directory.
+                self.citations.clear();
+                self.citations_filtered.clear();
+                self.quick_sources.clear();
+                self.file_scope = None;
+                // A scan still running lists subdirectories of the old cwd.
+                self.dir_scanning = None;
+                self.dir_truncated = false;
             }
         }
         self.mode = Mode::Search;
     }
 
+    /// Moves the current (or last answered) quick question into chat mode so
+    /// it can be re-asked against the full document context.
+    pub fn escalate_to_chat(&mut self) -> bool {
+        if self.quick_streaming || self.chat_streaming || self.api_key.is_none() {
+            return false;
+        }
+        let question = if self.quick_query.is_empty() {
+            match self.quick_history.last() {
+                Some((q, _)) => q.clone(),
+                None => return false,
+            }
+        } else {
+            std::mem::take(&mut self.quick_query)
+        };
+
+        self.show_prompt = false;
+        self.chat_input = question;
+        self.mode = Mode::Chat;
+        true
+    }
+
     pub fn start_quick_answer(&mut self) {
         if self.quick_query.is_empty() || self.quick_streaming || self.api_key.is_none() {
             return;
@@ -533,7 +1862,11 @@ DOCUMENTS:
     }
 
     pub fn append_quick_response(&mut self, text: &str) {
-        if text == "\n[DONE]" {
+        if let Some(err) = crate::chat::error_message(self.provider, text) {
+            self.quick_streaming = false;
+            self.quick_pending_query.clear();
+            self.quick_response.push_str(&format!("\n\n[error: {}]", err));
+        } else if text == DONE_SENTINEL {
             self.quick_streaming = false;
             if !self.quick_pending_query.is_empty() && !self.quick_response.is_empty() {
                 self.quick_history.push((self.quick_pending_query.clone(), self.quick_response.clone()));
@@ -545,23 +1878,103 @@ DOCUMENTS:
     }
 
     pub fn cancel_quick(&mut self) {
+        self.quick_buffer.clear();
         if self.quick_streaming {
             self.quick_streaming = false;
             self.quick_response.push_str("\n\n[cancelled]");
         }
     }
 
+    pub fn request_rag_rebuild(&mut self) {
+        if self.config.confirm_rebuild {
+            self.confirm_rebuild_pending = true;
+        } else {
+            self.rebuild_rag_index();
+        }
+    }
+
+    pub fn resolve_rag_rebuild(&mut self, confirmed: bool) {
+        self.confirm_rebuild_pending = false;
+        if confirmed {
+            self.rebuild_rag_index();
+        }
+    }
+
     pub fn rebuild_rag_index(&mut self) {
-        if let Some(cache_dir) = dirs::cache_dir() {
-            let _ = std::fs::remove_dir_all(cache_dir.join("finder"));
+        crate::rag::clear_cache(&self.dirs, &self.cwd);
+        if !self.from_archive {
+            self.loaded_files = load_md_files(&self.cwd, &config::extensions(&self.cwd));
         }
-        self.loaded_files = load_md_files(&self.cwd);
-        self.rag_index = RagIndex::new(&self.loaded_files, &self.cwd);
+        self.start_rag_index();
         self.quick_sources.clear();
-        self.status_message = Some((
-            format!("Index rebuilt ({} files)", self.loaded_files.len()),
-            std::time::Instant::now(),
-        ));
+    }
+
+    /// Opens the RAG index, updating it on a background thread when the
+    /// cache is stale so the UI stays responsive. Quick answers use the last
+    /// cached index until the update lands.
+    fn start_rag_index(&mut self) {
+        // Two writers can't share the index; run again once this one lands.
+        if self.rag_building.is_some() {
+            self.rag_queued = true;
+            return;
+        }
+
+        let cache_dir = crate::rag::get_cache_dir(&self.dirs, &self.cwd, self.config.sidecar_index);
+        if !crate::rag::needs_rebuild(&self.loaded_files, &self.cwd, &cache_dir) {
+            let index = RagIndex::new(&self.loaded_files, &self.cwd, &cache_dir);
+            if !index.is_cached() {
+                self.status_message = Some((IN_MEMORY_INDEX.to_string(), std::time::Instant::now()));
+            }
+            self.rag_index = Some(index);
+            self.rag_building = None;
+            return;
+        }
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let files = self.loaded_files.clone();
+        let cwd = self.cwd.clone();
+        self.rag_index = RagIndex::open_cached(&cache_dir);
+        std::thread::spawn(move || {
+            let _ = tx.send(RagIndex::new(&files, &cwd, &cache_dir));
+        });
+        self.rag_building = Some(rx);
+    }
+
+    pub fn poll_rag_index(&mut self) {
+        let Some(rx) = &self.rag_building else { return };
+        let message = match rx.try_recv() {
+            Ok(index) if !index.is_cached() => {
+                self.rag_index = Some(index);
+                IN_MEMORY_INDEX.to_string()
+            }
+            Ok(index) => {
+                self.rag_index = Some(index);
+                format!("Search index ready ({} files)", self.loaded_files.len())
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => return,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => "Building the search index failed".to_string(),
+        };
+        self.rag_building = None;
+        self.status_message = Some((message, std::time::Instant::now()));
+
+        if std::mem::take(&mut self.rag_queued) {
+            self.start_rag_index();
+        }
+    }
+
+    /// Blocks until a background index update lands, for one-shot answers
+    /// that can't wait for the next tick.
+    pub fn wait_for_rag_index(&mut self) {
+        if let Some(rx) = self.rag_building.take() {
+            if let Ok(index) = rx.recv() {
+                self.rag_index = Some(index);
+            }
+        }
+    }
+
+    /// Number of files being indexed while a background rebuild runs.
+    pub fn rag_indexing(&self) -> Option<usize> {
+        self.rag_building.as_ref().map(|_| self.loaded_files.len())
     }
 
     pub fn new_quick_conversation(&mut self) {
@@ -576,12 +1989,30 @@ DOCUMENTS:
     }
 
     pub fn prepare_quick_search(&mut self) {
-        self.quick_sources = self.rag_index.search_chunks(&self.quick_query, 20);
+        let Some(index) = &self.rag_index else {
+            self.quick_sources.clear();
+            self.quick_sources_relaxed = false;
+            self.status_message = Some((
+                "Search index not ready yet, answering without sources".to_string(),
+                std::time::Instant::now(),
+            ));
+            return;
+        };
+        let (sources, relaxed) = index.search_chunks_relaxed(
+            &self.quick_query,
+            self.config.source_limit,
+            self.config.source_threshold,
+        );
+        self.quick_sources = sources;
+        self.quick_sources_relaxed = relaxed;
         self.quick_sources_selected = 0;
     }
 
     pub fn build_quick_messages(&self) -> Vec<ChatMessage> {
+        // Exact matches come first by score and fuzzy ones after them (their
+        // scores don't compare), so the model gets the best of them.
         let relevant_context: String = self.quick_sources.iter()
+            .take(self.config.prompt_source_limit)
             .map(|c| format!("[{}:{}] {}\n\n", c.file, c.line, c.content))
             .collect();
 
@@ -622,6 +2053,23 @@ RELEVANT CONTEXT:
         self.quick_sources_expanded = !self.quick_sources_expanded;
     }
 
+    /// Selects row `idx` of the citation, directory or source list. True
+    /// when it was already selected, so a second click opens it.
+    pub fn select_list_item(&mut self, idx: usize) -> bool {
+        let selected = match self.mode {
+            Mode::Citations => &mut self.citations_selected,
+            Mode::DirectoryPicker => &mut self.dir_selected,
+            Mode::QuickAnswer => &mut self.quick_sources_selected,
+            _ => return false,
+        };
+        let again = *selected == idx;
+        *selected = idx;
+        if !again {
+            self.citations_preview_offset = None;
+        }
+        again
+    }
+
     pub fn quick_sources_up(&mut self) {
         if self.quick_sources_selected > 0 {
             self.quick_sources_selected -= 1;
@@ -634,13 +2082,251 @@ RELEVANT CONTEXT:
         }
     }
 
-    pub fn open_quick_source(&mut self) {
-        if let Some(chunk) = self.quick_sources.get(self.quick_sources_selected) {
-            let file_path = self.cwd.join(&chunk.file);
-            let _ = std::process::Command::new("nvim")
-                .arg(format!("+{}", chunk.line))
-                .arg(&file_path)
-                .status();
+    /// Path and line of the selected quick-answer source, unless it was
+    /// already opened in a split. The caller suspends the TUI around the
+    /// editor.
+    pub fn quick_source_to_open(&mut self) -> Option<(PathBuf, usize)> {
+        let chunk = self.quick_sources.get(self.quick_sources_selected)?;
+        let file_path = self.resolve(&chunk.file);
+        if self.config.open_in_split && editor::open_in_split(&file_path, chunk.line, None) {
+            return None;
+        }
+        Some((file_path, chunk.line))
+    }
+}
+
+/// `20-25,30` as `[(20, 25), (30, 30)]`.
+fn parse_line_spans(spec: &str) -> Vec<(usize, usize)> {
+    spec.split(',')
+        .filter_map(|part| {
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            let start: usize = start.trim().parse().ok()?;
+            let end: usize = end.trim().parse().ok()?;
+            Some((start.max(1), end.max(start).max(1)))
+        })
+        .collect()
+}
+
+pub fn preview_window_start(target_line: usize, visible: usize, total: usize) -> usize {
+    let centered = target_line.saturating_sub(1).saturating_sub(visible / 2);
+    centered.min(total.saturating_sub(visible))
+}
+
+fn infer_citation_column(preceding: &str, line: &str) -> Option<usize> {
+    let sentence = preceding
+        .rsplit(['.', '\n', ']'])
+        .next()
+        .unwrap_or(preceding);
+
+    sentence
+        .split(|c: char| !c.is_alphanumeric() && c != '_' && c != '-')
+        .rev()
+        .filter(|word| word.chars().count() >= 4)
+        .find_map(|word| find_ignoring_case(line, word))
+        .map(|byte_idx| byte_idx + 1)
+}
+
+/// Byte offset of `needle` in `haystack`, ignoring case. Compares lowercase
+/// characters without lowercasing `haystack` first, since that can change
+/// its length (`İ` becomes two characters) and shift the offset.
+fn find_ignoring_case(haystack: &str, needle: &str) -> Option<usize> {
+    let needle: Vec<char> = needle.chars().flat_map(char::to_lowercase).collect();
+    haystack.char_indices().map(|(i, _)| i).find(|&i| {
+        let mut rest = haystack[i..].chars().flat_map(char::to_lowercase);
+        needle.iter().all(|&c| rest.next() == Some(c))
+    })
+}
+
+fn file_extension(file: &str) -> Option<String> {
+    std::path::Path::new(file)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+}
+
+fn snippet_bounds(lines: &[&str], target: usize) -> (usize, usize) {
+    let mut fence_start: Option<usize> = None;
+    for (i, line) in lines.iter().enumerate() {
+        if !line.trim_start().starts_with("```") {
+            continue;
+        }
+        match fence_start {
+            Some(open) if open < target && target < i => return (open + 1, i),
+            Some(_) => fence_start = None,
+            None if i > target => break,
+            None => fence_start = Some(i),
+        }
+    }
+
+    let mut start = target;
+    while start > 0 && !lines[start - 1].trim().is_empty() {
+        start -= 1;
+    }
+    let mut end = target + 1;
+    while end < lines.len() && !lines[end].trim().is_empty() {
+        end += 1;
+    }
+    (start, end)
+}
+
+/// Strips the leading whitespace all non-blank lines share, counted in
+/// characters so mixed or multi-byte whitespace never splits a character.
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|l| {
+            let start = l.char_indices().nth(indent).map_or(l.len(), |(i, _)| i);
+            l[start..].trim_end()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Subdirectories of `cwd` up to `depth` levels down, sorted, and whether
+/// more than `limit` of them were found.
+fn scan_subdirectories(cwd: &std::path::Path, depth: usize, limit: usize) -> (Vec<PathBuf>, bool) {
+    let walker = crate::search::walker(cwd)
+        .max_depth(Some(depth))
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut dirs = Vec::new();
+    for result in walker {
+        let Ok(entry) = result else { continue };
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let Ok(rel) = path.strip_prefix(cwd) else { continue };
+        if rel.as_os_str().is_empty() {
+            continue;
+        }
+        if dirs.len() == limit {
+            dirs.sort();
+            return (dirs, true);
+        }
+        dirs.push(rel.to_path_buf());
+    }
+
+    dirs.sort();
+    (dirs, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedent_strips_shared_indent() {
+        assert_eq!(dedent(&["    fn a() {", "        b();", "", "    }"]), "fn a() {\n    b();\n\n}");
+    }
+
+    #[test]
+    fn dedent_counts_multibyte_whitespace_as_one_char() {
+        // U+3000 is three bytes; slicing by bytes would cut into it.
+        assert_eq!(dedent(&["\u{3000}a", " b"]), "a\nb");
+        assert_eq!(dedent(&["\u{3000}\u{3000}a", "  b"]), "a\nb");
+    }
+
+    #[test]
+    fn citation_columns_are_bytes_into_the_original_line() {
+        assert_eq!(infer_citation_column("See the Notes", "İstanbul notes"), Some(11));
+        assert_eq!(infer_citation_column("nothing here", "İstanbul notes"), None);
+    }
+
+    #[test]
+    fn paths_resolve_against_the_picked_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        std::fs::write(root.join("notes.md"), "old").unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub").join("notes.md"), "new").unwrap();
+        let state = tempfile::tempdir().unwrap();
+
+        let mut app = App::with_dirs(root.clone(), UserDirs::under(state.path()));
+        app.config.autosave_secs = 0;
+        app.inline_context = false;
+        app.dir_entries = vec![PathBuf::from("sub")];
+        app.select_directory();
+
+        let opened = root.join("sub").join("notes.md");
+        assert_eq!(app.cwd, root.join("sub"));
+        assert_eq!(app.resolve("notes.md"), opened);
+
+        app.results = vec![SearchEntry {
+            file: "notes.md".to_string(),
+            line_num: 1,
+            content: "new".to_string(),
+            match_indices: Vec::new(),
+            match_groups: Vec::new(),
+            rank: 0,
+            lang: None,
+        }];
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while matches!(app.previews.get(&opened), crate::preview::Preview::Loading) && std::time::Instant::now() < deadline {
+            app.poll_previews();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(matches!(app.previews.get(&opened), crate::preview::Preview::Ready("new")));
+    }
+
+    #[test]
+    fn compare_scrolls_as_far_as_the_files_drawn() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        std::fs::write(root.join("a.md"), "one").unwrap();
+        std::fs::write(root.join("b.md"), "one").unwrap();
+        let state = tempfile::tempdir().unwrap();
+
+        let mut app = App::with_dirs(root.clone(), UserDirs::under(state.path()));
+        // Grown since it was indexed; the panes show the file as it is now.
+        std::fs::write(root.join("b.md"), "one\ntwo\nthree\nfour").unwrap();
+        app.compare_files = Some(("a.md".to_string(), "b.md".to_string()));
+        app.mode = Mode::Compare;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while matches!(app.previews.get(&root.join("b.md")), crate::preview::Preview::Loading)
+            && std::time::Instant::now() < deadline
+        {
+            app.poll_previews();
+            std::thread::sleep(std::time::Duration::from_millis(10));
         }
+
+        app.scroll_compare(10);
+        assert_eq!(app.compare_scroll, 3);
+    }
+
+    #[test]
+    fn wheel_over_the_preview_scrolls_it_and_keeps_the_selection() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        let content: Vec<String> = (1..=50).map(|n| format!("line {}", n)).collect();
+        std::fs::write(root.join("notes.md"), content.join("\n")).unwrap();
+        let state = tempfile::tempdir().unwrap();
+
+        let mut app = App::with_dirs(root.clone(), UserDirs::under(state.path()));
+        app.inline_context = false;
+        app.query = "line".to_string();
+        app.update_search();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while matches!(app.previews.get(&root.join("notes.md")), crate::preview::Preview::Loading)
+            && std::time::Instant::now() < deadline
+        {
+            app.poll_previews();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let selected = app.selected;
+
+        app.scroll_preview(3, 10);
+        assert_eq!(app.selected, selected);
+        assert_eq!(app.preview_offset, Some((selected, 3)));
+        app.preview_start_drawn.set(3);
+        app.scroll_preview(100, 10);
+        assert_eq!(app.preview_offset, Some((selected, 40)));
     }
 }
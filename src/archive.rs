@@ -0,0 +1,97 @@
+use crate::search::LoadedFile;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+pub fn load_archive(path: &Path, extensions: &[String]) -> io::Result<Vec<LoadedFile>> {
+    let name = path.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        load_zip(path, extensions)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        load_tar(flate2::read::GzDecoder::new(File::open(path)?), extensions)
+    } else if name.ends_with(".tar") {
+        load_tar(File::open(path)?, extensions)
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "expected a .zip, .tar, .tar.gz or .tgz archive"))
+    }
+}
+
+/// Directory the archive is extracted to, unique per archive path, size
+/// and mtime. The search index is cached per directory, so a changed
+/// archive also gets a fresh index.
+pub fn extract_dir(path: &Path) -> io::Result<PathBuf> {
+    let meta = std::fs::metadata(path)?;
+    let modified = meta.modified()?.duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let key = format!("{}:{}:{}", path.to_string_lossy(), meta.len(), modified);
+    let hash = format!("{:x}", md5::compute(key.as_bytes()));
+    Ok(std::env::temp_dir().join(format!("finder-archive-{}", &hash[..16])))
+}
+
+/// Writes `files` under `dir` so the editor can open them. Files already
+/// there are kept, since `dir` changes whenever the archive does.
+pub fn extract(dir: &Path, files: &[LoadedFile]) -> io::Result<()> {
+    for file in files {
+        let path = dir.join(&file.name);
+        if path.exists() {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, &file.content)?;
+    }
+    Ok(())
+}
+
+fn has_extension(name: &str, extensions: &[String]) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|ext| extensions.contains(&ext.to_string_lossy().to_lowercase()))
+}
+
+fn load_zip(path: &Path, extensions: &[String]) -> io::Result<Vec<LoadedFile>> {
+    let mut zip = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+    let mut files = Vec::new();
+
+    for i in 0..zip.len() {
+        let Ok(mut entry) = zip.by_index(i) else { continue };
+        if !entry.is_file() || !has_extension(entry.name(), extensions) {
+            continue;
+        }
+        // enclosed_name rejects absolute paths and `..` components.
+        let Some(name) = entry.enclosed_name() else { continue };
+        let name = name.to_string_lossy().to_string();
+        let mut content = String::new();
+        if entry.read_to_string(&mut content).is_ok() {
+            files.push(LoadedFile { name, content });
+        }
+    }
+
+    Ok(files)
+}
+
+fn load_tar(reader: impl Read, extensions: &[String]) -> io::Result<Vec<LoadedFile>> {
+    let mut tar = tar::Archive::new(reader);
+    let mut files = Vec::new();
+
+    for entry in tar.entries()? {
+        let Ok(mut entry) = entry else { continue };
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Ok(path) = entry.path() else { continue };
+        if path.is_absolute() || path.components().any(|c| c == std::path::Component::ParentDir) {
+            continue;
+        }
+        let name = path.to_string_lossy().trim_start_matches("./").to_string();
+        if !has_extension(&name, extensions) {
+            continue;
+        }
+        let mut content = String::new();
+        if entry.read_to_string(&mut content).is_ok() {
+            files.push(LoadedFile { name, content });
+        }
+    }
+
+    Ok(files)
+}
@@ -1,10 +1,211 @@
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
+use crate::config::UserDirs;
 use std::path::Path;
 use tokio::sync::mpsc;
 
-const API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
-const MODEL: &str = "google/gemini-2.5-flash-lite";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Attempts to reach the API before giving up; retries only happen before
+/// any response arrives.
+const CONNECT_ATTEMPTS: u32 = 3;
+
+pub const DONE_SENTINEL: &str = "\n[DONE]";
+pub const ERROR_SENTINEL: &str = "\n[ERROR] ";
+/// Sent instead of `ERROR_SENTINEL` when the API rejected the key.
+pub const AUTH_ERROR_SENTINEL: &str = "\n[AUTH_ERROR] ";
+
+/// What to show for an error chunk from `stream_chat`, `None` for any
+/// other chunk.
+pub fn error_message(provider: Provider, chunk: &str) -> Option<String> {
+    if let Some(detail) = chunk.strip_prefix(AUTH_ERROR_SENTINEL) {
+        let var = provider.key_var().unwrap_or("the API key");
+        return Some(format!("API key rejected, check {} ({})", var, detail));
+    }
+    chunk.strip_prefix(ERROR_SENTINEL).map(str::to_string)
+}
+
+enum ChatError {
+    /// 401 or 403: the key is missing permissions or wrong.
+    Auth(String),
+    Other(String),
+}
+
+impl From<String> for ChatError {
+    fn from(message: String) -> Self {
+        ChatError::Other(message)
+    }
+}
+
+/// Chat API backend, selected with `FINDER_PROVIDER`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    OpenRouter,
+    OpenAI,
+    Anthropic,
+    OllamaLocal,
+}
+
+impl Provider {
+    /// The provider named by `FINDER_PROVIDER`, in any case, and a warning
+    /// when the name is not one we know. Defaults to OpenRouter.
+    pub fn from_env() -> (Self, Option<String>) {
+        let Ok(name) = std::env::var("FINDER_PROVIDER") else {
+            return (Provider::OpenRouter, None);
+        };
+        let provider = match name.trim().to_lowercase().as_str() {
+            "openai" => Provider::OpenAI,
+            "anthropic" => Provider::Anthropic,
+            "ollama" => Provider::OllamaLocal,
+            "openrouter" | "" => Provider::OpenRouter,
+            _ => {
+                let warning = format!("unknown FINDER_PROVIDER `{}`, using OpenRouter", name.trim());
+                return (Provider::OpenRouter, Some(warning));
+            }
+        };
+        (provider, None)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Provider::OpenRouter => "OpenRouter",
+            Provider::OpenAI => "OpenAI",
+            Provider::Anthropic => "Anthropic",
+            Provider::OllamaLocal => "Ollama",
+        }
+    }
+
+    fn url(self) -> String {
+        match self {
+            Provider::OpenRouter => "https://openrouter.ai/api/v1/chat/completions".to_string(),
+            Provider::OpenAI => "https://api.openai.com/v1/chat/completions".to_string(),
+            Provider::Anthropic => "https://api.anthropic.com/v1/messages".to_string(),
+            Provider::OllamaLocal => format!("{}/api/chat", ollama_host()),
+        }
+    }
+
+    /// Environment variable holding the API key; `None` when no key is needed.
+    pub fn key_var(self) -> Option<&'static str> {
+        match self {
+            Provider::OpenRouter => Some("OPENROUTER_API_KEY"),
+            Provider::OpenAI => Some("OPENAI_API_KEY"),
+            Provider::Anthropic => Some("ANTHROPIC_API_KEY"),
+            Provider::OllamaLocal => None,
+        }
+    }
+
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Provider::OpenRouter => "google/gemini-2.5-flash-lite",
+            Provider::OpenAI => "gpt-4o-mini",
+            Provider::Anthropic => "claude-3-5-haiku-latest",
+            Provider::OllamaLocal => "llama3",
+        }
+    }
+
+    fn request(self, client: &reqwest::Client, api_key: &str, body: &serde_json::Value) -> reqwest::RequestBuilder {
+        let request = client.post(self.url()).json(body);
+        match self {
+            Provider::Anthropic => request
+                .header("x-api-key", api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION),
+            _ if api_key.is_empty() => request,
+            _ => request.header("Authorization", format!("Bearer {}", api_key)),
+        }
+    }
+
+    fn body(self, model: &str, messages: &[ChatMessage]) -> serde_json::Value {
+        if self == Provider::OllamaLocal {
+            return serde_json::json!({
+                "model": model,
+                "messages": messages,
+                "stream": true,
+            });
+        }
+
+        if self != Provider::Anthropic {
+            return serde_json::json!({
+                "model": model,
+                "messages": messages,
+                "stream": true,
+                "max_tokens": 4096,
+            });
+        }
+
+        // Anthropic takes the system prompt as a separate field.
+        let system: Vec<&str> = messages
+            .iter()
+            .filter(|m| m.role == "system")
+            .map(|m| m.content.as_str())
+            .collect();
+        let turns: Vec<&ChatMessage> = messages.iter().filter(|m| m.role != "system").collect();
+        serde_json::json!({
+            "model": model,
+            "system": system.join("\n\n"),
+            "messages": turns,
+            "stream": true,
+            "max_tokens": 4096,
+        })
+    }
+
+    /// JSON payload of one stream line: SSE `data:` lines, or every line of
+    /// Ollama's newline-delimited JSON.
+    fn frame_data(self, line: &str) -> Option<&str> {
+        if self == Provider::OllamaLocal {
+            Some(line)
+        } else {
+            line.strip_prefix("data: ")
+        }
+    }
+
+    fn parse_frame(self, data: &str) -> Frame {
+        if self == Provider::OllamaLocal {
+            return match serde_json::from_str::<OllamaChunk>(data) {
+                Ok(OllamaChunk { error: Some(e), .. }) => Frame::Error(e),
+                Ok(OllamaChunk { done: true, .. }) => Frame::Done,
+                Ok(OllamaChunk { message: Some(m), .. }) => Frame::Text(m.content),
+                _ => Frame::Skip,
+            };
+        }
+
+        if let Ok(err) = serde_json::from_str::<StreamError>(data) {
+            return Frame::Error(err.describe());
+        }
+
+        if self == Provider::Anthropic {
+            return match serde_json::from_str::<AnthropicEvent>(data) {
+                Ok(AnthropicEvent::ContentBlockDelta { delta }) => delta.text.map_or(Frame::Skip, Frame::Text),
+                Ok(AnthropicEvent::MessageStop) => Frame::Done,
+                _ => Frame::Skip,
+            };
+        }
+
+        if data == "[DONE]" {
+            return Frame::Done;
+        }
+        serde_json::from_str::<StreamResponse>(data)
+            .ok()
+            .and_then(|parsed| parsed.choices.into_iter().next())
+            .and_then(|choice| choice.delta.content)
+            .map_or(Frame::Skip, Frame::Text)
+    }
+}
+
+fn ollama_host() -> String {
+    std::env::var("OLLAMA_HOST")
+        .ok()
+        .map(|h| h.trim().trim_end_matches('/').to_string())
+        .filter(|h| !h.is_empty())
+        .map(|h| if h.contains("://") { h } else { format!("http://{}", h) })
+        .unwrap_or_else(|| "http://localhost:11434".to_string())
+}
+
+enum Frame {
+    Text(String),
+    Done,
+    Error(String),
+    Skip,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
@@ -27,8 +228,58 @@ struct StreamResponse {
     choices: Vec<Choice>,
 }
 
-pub fn find_api_key() -> Option<String> {
-    if let Ok(key) = std::env::var("OPENROUTER_API_KEY") {
+#[derive(Debug, Deserialize)]
+struct AnthropicDelta {
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicEvent {
+    ContentBlockDelta { delta: AnthropicDelta },
+    MessageStop,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChunk {
+    message: Option<ChatMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    message: Option<String>,
+    code: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamError {
+    error: ErrorBody,
+}
+
+impl StreamError {
+    fn describe(&self) -> String {
+        let message = self.error.message.as_deref().unwrap_or("unknown error");
+        match &self.error.code {
+            Some(code) => format!("{} ({})", message, code),
+            None => message.to_string(),
+        }
+    }
+}
+
+/// Looks up the provider's API key in the environment, then `./.env`,
+/// `~/.env` and `.env` in finder's config directory. Providers that need
+/// no key get an empty one.
+pub fn find_api_key(provider: Provider, dirs: &UserDirs) -> Option<String> {
+    let Some(var) = provider.key_var() else {
+        return Some(String::new());
+    };
+
+    if let Ok(key) = std::env::var(var) {
         if !key.is_empty() {
             return Some(key);
         }
@@ -36,11 +287,12 @@ pub fn find_api_key() -> Option<String> {
 
     let paths = [
         std::env::current_dir().ok().map(|p| p.join(".env")),
-        dirs::home_dir().map(|p| p.join(".env")),
+        dirs.home.as_ref().map(|p| p.join(".env")),
+        dirs.config.as_ref().map(|p| p.join(".env")),
     ];
 
     for path in paths.into_iter().flatten() {
-        if let Some(key) = read_env_file(&path) {
+        if let Some(key) = read_env_file(&path, var) {
             return Some(key);
         }
     }
@@ -48,56 +300,121 @@ pub fn find_api_key() -> Option<String> {
     None
 }
 
-fn read_env_file(path: &Path) -> Option<String> {
+/// Finds `var` in a dotenv file. Accepts `export VAR=...`, single or
+/// double quotes (which may contain `=` and `#`), and comments on their own
+/// line or after an unquoted value.
+fn read_env_file(path: &Path, var: &str) -> Option<String> {
     let content = std::fs::read_to_string(path).ok()?;
     for line in content.lines() {
         let line = line.trim();
-        if line.starts_with("OPENROUTER_API_KEY") {
-            if let Some(value) = line.split('=').nth(1) {
-                let value = value.trim().trim_matches('"').trim_matches('\'');
-                if !value.is_empty() {
-                    return Some(value.to_string());
-                }
-            }
+        if line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else { continue };
+        if key.trim() != var {
+            continue;
+        }
+        let value = env_value(value.trim());
+        if !value.is_empty() {
+            return Some(value.to_string());
         }
     }
     None
 }
 
+/// The text up to the closing quote of a quoted value, or an unquoted
+/// value up to a `#` that follows a space or tab.
+fn env_value(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some((inner, _)) = value.strip_prefix(quote).and_then(|v| v.split_once(quote)) {
+            return inner;
+        }
+    }
+    let end = value
+        .char_indices()
+        .find(|&(i, c)| c == '#' && value[..i].ends_with([' ', '\t']))
+        .map_or(value.len(), |(i, _)| i);
+    value[..end].trim()
+}
+
 pub async fn stream_chat(
+    provider: Provider,
     api_key: &str,
+    model: &str,
     messages: Vec<ChatMessage>,
     tx: mpsc::UnboundedSender<String>,
 ) -> Result<(), String> {
-    let client = reqwest::Client::new();
-
-    let body = serde_json::json!({
-        "model": MODEL,
-        "messages": messages,
-        "stream": true,
-        "max_tokens": 4096,
-    });
-
-    let response = client
-        .post(API_URL)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
-        .await
+    match stream_chat_inner(provider, api_key, model, messages, &tx).await {
+        Ok(()) => Ok(()),
+        Err(ChatError::Auth(e)) => {
+            let _ = tx.send(format!("{}{}", AUTH_ERROR_SENTINEL, e));
+            Err(e)
+        }
+        Err(ChatError::Other(e)) => {
+            let _ = tx.send(format!("{}{}", ERROR_SENTINEL, e));
+            Err(e)
+        }
+    }
+}
+
+async fn stream_chat_inner(
+    provider: Provider,
+    api_key: &str,
+    model: &str,
+    messages: Vec<ChatMessage>,
+    tx: &mpsc::UnboundedSender<String>,
+) -> Result<(), ChatError> {
+    let (connect_timeout, idle_timeout) = crate::config::request_timeouts();
+    let client = reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .read_timeout(idle_timeout)
+        .build()
         .map_err(|e| e.to_string())?;
 
+    let body = provider.body(model, &messages);
+
+    let mut attempt = 1;
+    let response = loop {
+        let result = provider.request(&client, api_key, &body).send().await;
+
+        match result {
+            Ok(response) => break response,
+            Err(e) if (e.is_connect() || e.is_timeout()) && attempt < CONNECT_ATTEMPTS => {
+                tokio::time::sleep(std::time::Duration::from_millis(500 * 2u64.pow(attempt - 1))).await;
+                attempt += 1;
+            }
+            Err(e) if e.is_connect() && provider == Provider::OllamaLocal => {
+                return Err(ChatError::Other(format!(
+                    "no Ollama server reachable at {} (start it with `ollama serve`)",
+                    ollama_host()
+                )));
+            }
+            Err(e) if e.is_timeout() => return Err(format!("request timed out ({})", e).into()),
+            Err(e) => return Err(e.to_string().into()),
+        }
+    };
+
     if !response.status().is_success() {
         let status = response.status();
         let text = response.text().await.unwrap_or_default();
-        return Err(format!("API error {}: {}", status, text));
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(ChatError::Auth(format!("{}: {}", status, text.trim())));
+        }
+        return Err(format!("API error {}: {}", status, text).into());
     }
 
     let mut stream = response.bytes_stream();
     let mut buffer = String::new();
 
     while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|e| e.to_string())?;
+        let chunk = chunk.map_err(|e| {
+            if e.is_timeout() {
+                format!("no data for {}s, connection stalled", idle_timeout.as_secs())
+            } else {
+                e.to_string()
+            }
+        })?;
         buffer.push_str(&String::from_utf8_lossy(&chunk));
 
         while let Some(line_end) = buffer.find('\n') {
@@ -108,23 +425,78 @@ pub async fn stream_chat(
                 continue;
             }
 
-            if line.starts_with("data: ") {
-                let data = &line[6..];
-                if data == "[DONE]" {
-                    let _ = tx.send("\n[DONE]".to_string());
-                    return Ok(());
-                }
-
-                if let Ok(parsed) = serde_json::from_str::<StreamResponse>(data) {
-                    if let Some(choice) = parsed.choices.first() {
-                        if let Some(content) = &choice.delta.content {
-                            let _ = tx.send(content.clone());
-                        }
+            if let Some(data) = provider.frame_data(&line) {
+                match provider.parse_frame(data) {
+                    Frame::Text(content) => {
+                        let _ = tx.send(content);
                     }
+                    Frame::Done => {
+                        let _ = tx.send(DONE_SENTINEL.to_string());
+                        return Ok(());
+                    }
+                    Frame::Error(e) => return Err(e.into()),
+                    Frame::Skip => {}
                 }
             }
         }
     }
 
+    // Some proxies close the connection without emitting [DONE].
+    let _ = tx.send(DONE_SENTINEL.to_string());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn read(content: &str, var: &str) -> Option<String> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        read_env_file(file.path(), var)
+    }
+
+    #[test]
+    fn keeps_equals_signs_inside_values() {
+        assert_eq!(read("KEY=a=b==\n", "KEY").as_deref(), Some("a=b=="));
+        assert_eq!(read("KEY=\"a=b\"\n", "KEY").as_deref(), Some("a=b"));
+    }
+
+    #[test]
+    fn trims_whitespace_around_keys_and_values() {
+        assert_eq!(read("  KEY =  secret  \n", "KEY").as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn strips_single_and_double_quotes() {
+        assert_eq!(read("KEY=\"secret\"\n", "KEY").as_deref(), Some("secret"));
+        assert_eq!(read("KEY='secret'\n", "KEY").as_deref(), Some("secret"));
+        assert_eq!(read("KEY='has # hash'\n", "KEY").as_deref(), Some("has # hash"));
+    }
+
+    #[test]
+    fn accepts_export_prefix() {
+        assert_eq!(read("export KEY=secret\n", "KEY").as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn skips_comment_lines() {
+        let content = "# KEY=commented\n  # KEY=indented\nKEY=secret\n";
+        assert_eq!(read(content, "KEY").as_deref(), Some("secret"));
+        assert_eq!(read("# KEY=commented\n", "KEY"), None);
+    }
+
+    #[test]
+    fn drops_inline_comments() {
+        assert_eq!(read("KEY=secret # note\n", "KEY").as_deref(), Some("secret"));
+        assert_eq!(read("KEY=secret\t# note\n", "KEY").as_deref(), Some("secret"));
+        assert_eq!(env_value("abc#def"), "abc#def");
+    }
+
+    #[test]
+    fn ignores_other_keys_and_empty_values() {
+        assert_eq!(read("OTHER=x\nKEY_2=y\n", "KEY"), None);
+        assert_eq!(read("KEY=\nKEY=later\n", "KEY").as_deref(), Some("later"));
+    }
+}
@@ -0,0 +1,113 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Wsl,
+    MacOs,
+    Wayland,
+    Xclip,
+    Xsel,
+}
+
+impl Backend {
+    fn command(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Backend::Wsl => ("clip.exe", &[]),
+            Backend::MacOs => ("pbcopy", &[]),
+            Backend::Wayland => ("wl-copy", &[]),
+            Backend::Xclip => ("xclip", &["-selection", "clipboard"]),
+            Backend::Xsel => ("xsel", &["--clipboard", "--input"]),
+        }
+    }
+}
+
+/// What the clipboard tools depend on in the running session.
+#[derive(Debug, Clone, Copy, Default)]
+struct Session {
+    macos: bool,
+    wsl: bool,
+    wayland: bool,
+}
+
+impl Session {
+    fn detect() -> Self {
+        Self {
+            macos: cfg!(target_os = "macos"),
+            wsl: std::path::Path::new("/mnt/c/WINDOWS/system32/clip.exe").exists(),
+            wayland: std::env::var_os("WAYLAND_DISPLAY").is_some(),
+        }
+    }
+}
+
+/// Clipboard tools to try in order. The X11 tools always come last since
+/// they also work under XWayland.
+fn detect_backends(session: Session) -> Vec<Backend> {
+    let mut backends = Vec::new();
+    if session.macos {
+        backends.push(Backend::MacOs);
+    }
+    if session.wsl {
+        backends.push(Backend::Wsl);
+    }
+    if session.wayland {
+        backends.push(Backend::Wayland);
+    }
+    backends.extend([Backend::Xclip, Backend::Xsel]);
+    backends
+}
+
+/// Copies `text` with the first clipboard tool that works. Returns false
+/// when none of them did.
+pub fn copy(text: &str) -> bool {
+    let clean_text: String = text
+        .chars()
+        .filter(|c| !matches!(*c, '│' | '┌' | '┐' | '└' | '┘' | '├' | '┤' | '┬' | '┴' | '┼' | '─' | '║' | '═'))
+        .collect();
+
+    for backend in detect_backends(Session::detect()) {
+        let (cmd, args) = backend.command();
+        let Ok(mut child) = Command::new(cmd)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+
+        let written = child.stdin.take().is_some_and(|mut stdin| stdin.write_all(clean_text.as_bytes()).is_ok());
+        if child.wait().is_ok_and(|status| status.success()) && written {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x11_uses_xclip_then_xsel() {
+        assert_eq!(detect_backends(Session::default()), [Backend::Xclip, Backend::Xsel]);
+    }
+
+    #[test]
+    fn wayland_tries_wl_copy_first() {
+        let session = Session { wayland: true, ..Session::default() };
+        assert_eq!(detect_backends(session), [Backend::Wayland, Backend::Xclip, Backend::Xsel]);
+    }
+
+    #[test]
+    fn macos_tries_pbcopy_first() {
+        let session = Session { macos: true, ..Session::default() };
+        assert_eq!(detect_backends(session), [Backend::MacOs, Backend::Xclip, Backend::Xsel]);
+    }
+
+    #[test]
+    fn wsl_tries_clip_exe_before_wayland() {
+        let session = Session { wsl: true, wayland: true, ..Session::default() };
+        assert_eq!(detect_backends(session), [Backend::Wsl, Backend::Wayland, Backend::Xclip, Backend::Xsel]);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::app::Mode;
+use crate::chat::Provider;
+use crate::theme::{Theme, ThemeConfig};
+use crate::search::EntryMode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONFIG_FILE: &str = ".finder.toml";
+
+pub struct Config {
+    pub citation_context: usize,
+    pub sidecar_index: bool,
+    pub autosave_secs: usize,
+    pub open_in_split: bool,
+    pub entry_mode: EntryMode,
+    pub confirm_rebuild: bool,
+    pub default_mode: Mode,
+    pub citations: bool,
+    pub copy_trimmed: bool,
+    pub inline_context: bool,
+    pub context_lines: usize,
+    pub multi_highlight: bool,
+    pub typewriter_cps: usize,
+    pub wrap_lines: bool,
+    pub preserve_indent: bool,
+    pub keep_chat_input: bool,
+    pub edit_loop: bool,
+    pub source_threshold: f32,
+    pub source_limit: usize,
+    pub prompt_source_limit: usize,
+    pub context_budget: usize,
+    pub chat_retrieval: bool,
+    pub watch: bool,
+    pub dir_depth: usize,
+    pub dir_limit: usize,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            citation_context: env_usize("FINDER_CITATION_CONTEXT", 0),
+            sidecar_index: env_bool("FINDER_SIDECAR_INDEX", false),
+            autosave_secs: env_usize("FINDER_AUTOSAVE_SECS", 0),
+            open_in_split: env_bool("FINDER_OPEN_IN_SPLIT", false),
+            entry_mode: match std::env::var("FINDER_SEARCH_UNIT").as_deref() {
+                Ok("section") => EntryMode::Section,
+                _ => EntryMode::Line,
+            },
+            confirm_rebuild: env_bool("FINDER_CONFIRM_REBUILD", true),
+            default_mode: std::env::var("FINDER_DEFAULT_MODE")
+                .ok()
+                .and_then(|m| Mode::from_name(&m))
+                .unwrap_or(Mode::Search),
+            citations: env_bool("FINDER_CITATIONS", true),
+            copy_trimmed: env_bool("FINDER_COPY_TRIMMED", true),
+            inline_context: std::env::var("FINDER_RESULTS_LAYOUT").as_deref() == Ok("inline"),
+            context_lines: env_usize("FINDER_CONTEXT_LINES", 1).max(1),
+            multi_highlight: env_bool("FINDER_MULTI_HIGHLIGHT", false),
+            typewriter_cps: env_usize("FINDER_TYPEWRITER_CPS", 0),
+            wrap_lines: env_bool("FINDER_WRAP", false),
+            preserve_indent: env_bool("FINDER_PRESERVE_INDENT", true),
+            keep_chat_input: env_bool("FINDER_KEEP_CHAT_INPUT", false),
+            edit_loop: env_bool("FINDER_EDIT_LOOP", false),
+            source_threshold: env_usize("FINDER_SOURCE_THRESHOLD", 30).min(100) as f32 / 100.0,
+            source_limit: env_usize("FINDER_SOURCE_LIMIT", 20).max(1),
+            prompt_source_limit: env_usize("FINDER_PROMPT_SOURCE_LIMIT", 20),
+            context_budget: env_usize("FINDER_CONTEXT_BUDGET", 400_000),
+            chat_retrieval: env_bool("FINDER_CHAT_RETRIEVAL", false),
+            watch: env_bool("FINDER_WATCH", false),
+            dir_depth: env_usize("FINDER_DIR_DEPTH", 5).max(1),
+            dir_limit: env_usize("FINDER_DIR_LIMIT", 5000).max(1),
+        }
+    }
+}
+
+/// Where finder keeps its own state, outside the searched directory.
+#[derive(Debug, Clone, Default)]
+pub struct UserDirs {
+    /// Search history, sessions and saved conversations.
+    pub data: Option<PathBuf>,
+    /// RAG indexes that don't live in a sidecar.
+    pub cache: Option<PathBuf>,
+    /// Holds a `.env` with API keys; also shortened to `~` in the header.
+    pub home: Option<PathBuf>,
+    /// Holds another `.env` with API keys.
+    pub config: Option<PathBuf>,
+}
+
+impl UserDirs {
+    /// The platform directories, each with a `finder` subdirectory. The
+    /// config directory is `$XDG_CONFIG_HOME`, or `~/.config` when it is
+    /// unset or empty, on every platform.
+    pub fn detect() -> Self {
+        let home = dirs::home_dir();
+        let config = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+            _ => home.as_ref().map(|home| home.join(".config")),
+        };
+        Self {
+            data: dirs::data_dir().map(|d| d.join("finder")),
+            cache: dirs::cache_dir().map(|d| d.join("finder")),
+            home,
+            config: config.map(|d| d.join("finder")),
+        }
+    }
+
+    /// Everything under `root`, so tests never touch the user's files.
+    #[cfg(test)]
+    pub fn under(root: &Path) -> Self {
+        Self {
+            data: Some(root.join("data")),
+            cache: Some(root.join("cache")),
+            home: Some(root.join("home")),
+            config: Some(root.join("config")),
+        }
+    }
+}
+
+fn env_usize(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+fn env_bool(name: &str, default: bool) -> bool {
+    match std::env::var(name).as_deref().map(str::trim) {
+        Ok("1" | "true" | "yes") => true,
+        Ok("0" | "false" | "no") => false,
+        _ => default,
+    }
+}
+
+#[derive(Default, Deserialize)]
+struct FileConfig {
+    extensions: Option<Vec<String>>,
+    model: Option<String>,
+    #[serde(default)]
+    keybindings: HashMap<String, String>,
+    #[serde(default)]
+    theme: ThemeConfig,
+}
+
+fn load_file_config(dir: &Path) -> FileConfig {
+    std::fs::read_to_string(dir.join(CONFIG_FILE))
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// File extensions to index in `dir`: `FINDER_EXTENSIONS` (comma-separated),
+/// then `extensions` in the directory's `.finder.toml`, then markdown only.
+pub fn extensions(dir: &Path) -> Vec<String> {
+    let configured = match std::env::var("FINDER_EXTENSIONS") {
+        Ok(value) => value.split(',').map(str::to_string).collect(),
+        Err(_) => load_file_config(dir).extensions.unwrap_or_default(),
+    };
+
+    let extensions: Vec<String> = configured
+        .iter()
+        .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+
+    if extensions.is_empty() {
+        vec!["md".to_string()]
+    } else {
+        extensions
+    }
+}
+
+/// Model for chat and quick answers: `FINDER_MODEL`, then `model` in the
+/// directory's `.finder.toml`, then the provider's default.
+pub fn model(dir: &Path, provider: Provider) -> String {
+    std::env::var("FINDER_MODEL")
+        .ok()
+        .or_else(|| load_file_config(dir).model)
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| provider.default_model().to_string())
+}
+
+/// Connect and idle timeouts for chat requests, from `FINDER_CONNECT_TIMEOUT`
+/// and `FINDER_IDLE_TIMEOUT` (seconds).
+pub fn request_timeouts() -> (Duration, Duration) {
+    (
+        Duration::from_secs(env_usize("FINDER_CONNECT_TIMEOUT", 10).max(1) as u64),
+        Duration::from_secs(env_usize("FINDER_IDLE_TIMEOUT", 60).max(1) as u64),
+    )
+}
+
+/// Key overrides from the `[keybindings]` table of the directory's
+/// `.finder.toml`, action name to key (see `keymap::Action`).
+pub fn keybindings(dir: &Path) -> HashMap<String, String> {
+    load_file_config(dir).keybindings
+}
+
+/// Colors from the `[theme]` table of the directory's `.finder.toml`, with
+/// `FINDER_THEME` choosing the preset (`dark`, `light` or `mono`), and
+/// warnings about an unknown preset or color.
+pub fn theme(dir: &Path) -> (Theme, Vec<String>) {
+    let mut config = load_file_config(dir).theme;
+    if let Ok(preset) = std::env::var("FINDER_THEME") {
+        config.preset = Some(preset);
+    }
+    Theme::from_config(&config)
+}
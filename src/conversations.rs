@@ -0,0 +1,87 @@
+use crate::chat::ChatMessage;
+use crate::config::UserDirs;
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// A chat saved with Ctrl+S. Each one is stored as JSON, to be reopened,
+/// next to a markdown copy for reading.
+#[derive(Serialize, Deserialize)]
+pub struct Conversation {
+    pub saved_at: u64,
+    pub messages: Vec<ChatMessage>,
+}
+
+impl Conversation {
+    /// First line of the first question.
+    pub fn title(&self) -> &str {
+        self.messages
+            .iter()
+            .find(|m| m.role == "user")
+            .and_then(|m| m.content.lines().next())
+            .unwrap_or("(empty)")
+    }
+
+    pub fn age(&self) -> String {
+        util::ago(self.saved_at)
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n", self.title());
+        for message in &self.messages {
+            let heading = if message.role == "user" { "You" } else { "Assistant" };
+            out.push_str(&format!("\n## {}\n\n{}\n", heading, message.content.trim()));
+        }
+        out
+    }
+}
+
+fn conversations_dir(dirs: &UserDirs) -> Option<PathBuf> {
+    dirs.data.as_ref().map(|d| d.join("conversations"))
+}
+
+/// Saves `messages` and returns the path of the markdown copy.
+pub fn save(dirs: &UserDirs, messages: &[ChatMessage]) -> io::Result<PathBuf> {
+    let dir = conversations_dir(dirs).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+    fs::create_dir_all(&dir)?;
+
+    let conversation = Conversation { saved_at: util::unix_now(), messages: messages.to_vec() };
+    let json = serde_json::to_string_pretty(&conversation).map_err(io::Error::other)?;
+    // Saves within the same second get a `-2`, `-3`, ... suffix.
+    let mut name = conversation.saved_at.to_string();
+    let mut suffix = 1;
+    let (path, mut file) = loop {
+        let path = dir.join(&name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(path.with_extension("json")) {
+            Ok(file) => break (path, file),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                suffix += 1;
+                name = format!("{}-{}", conversation.saved_at, suffix);
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    file.write_all(json.as_bytes())?;
+
+    let markdown = path.with_extension("md");
+    fs::write(&markdown, conversation.to_markdown())?;
+    Ok(markdown)
+}
+
+/// Saved conversations, newest first.
+pub fn list(dirs: &UserDirs) -> Vec<Conversation> {
+    let Some(entries) = conversations_dir(dirs).and_then(|dir| fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+
+    let mut conversations: Vec<Conversation> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| serde_json::from_str(&fs::read_to_string(path).ok()?).ok())
+        .collect();
+    conversations.sort_by_key(|c| std::cmp::Reverse(c.saved_at));
+    conversations
+}
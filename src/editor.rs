@@ -0,0 +1,88 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+enum Multiplexer {
+    Tmux,
+    Kitty,
+}
+
+fn detect_multiplexer() -> Option<Multiplexer> {
+    if std::env::var_os("TMUX").is_some() {
+        Some(Multiplexer::Tmux)
+    } else if std::env::var_os("KITTY_WINDOW_ID").is_some() {
+        Some(Multiplexer::Kitty)
+    } else {
+        None
+    }
+}
+
+fn position_arg(line: usize, column: Option<usize>) -> String {
+    match column {
+        Some(col) => format!("+call cursor({},{})", line, col),
+        None => format!("+{}", line),
+    }
+}
+
+pub fn open(path: &Path, line: usize, column: Option<usize>) -> io::Result<()> {
+    Command::new("nvim")
+        .arg(position_arg(line, column))
+        .arg(path)
+        .status()?;
+    Ok(())
+}
+
+pub fn open_in_split(path: &Path, line: usize, column: Option<usize>) -> bool {
+    let Some(multiplexer) = detect_multiplexer() else { return false };
+    let line_arg = position_arg(line, column);
+
+    let mut cmd = match multiplexer {
+        Multiplexer::Tmux => {
+            let mut cmd = Command::new("tmux");
+            cmd.args(["split-window", "-h", "nvim", &line_arg]);
+            cmd
+        }
+        Multiplexer::Kitty => {
+            let mut cmd = Command::new("kitty");
+            cmd.args(["@", "launch", "--location=vsplit", "nvim", &line_arg]);
+            cmd
+        }
+    };
+
+    cmd.arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Opens a web or mailto URL with the platform's default handler. Anything
+/// else is refused, so a link in an answer can never launch a program.
+pub fn open_external(target: &str) -> bool {
+    if !is_web_link(target) {
+        return false;
+    }
+    let mut cmd = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("rundll32");
+        cmd.arg("url.dll,FileProtocolHandler");
+        cmd
+    } else {
+        Command::new("xdg-open")
+    };
+
+    cmd.arg(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .is_ok()
+}
+
+/// An http(s) or mailto URL.
+pub fn is_web_link(target: &str) -> bool {
+    let lower = target.to_ascii_lowercase();
+    ["http://", "https://", "mailto:"].iter().any(|scheme| lower.starts_with(scheme))
+}
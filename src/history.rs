@@ -0,0 +1,41 @@
+use crate::config::UserDirs;
+use std::fs;
+use std::path::PathBuf;
+
+const MAX_ENTRIES: usize = 500;
+
+// Lives next to sessions rather than in the RAG cache, which Ctrl+R wipes.
+fn history_path(dirs: &UserDirs) -> Option<PathBuf> {
+    dirs.data.as_ref().map(|d| d.join("search_history"))
+}
+
+pub fn load(dirs: &UserDirs) -> Vec<String> {
+    history_path(dirs)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| content.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+pub fn record(dirs: &UserDirs, history: &mut Vec<String>, query: &str) {
+    let query = query.trim();
+    if query.is_empty() || query.contains('\n') || history.last().map(String::as_str) == Some(query) {
+        return;
+    }
+    history.push(query.to_string());
+    if history.len() > MAX_ENTRIES {
+        history.drain(..history.len() - MAX_ENTRIES);
+    }
+    save(dirs, history);
+}
+
+fn save(dirs: &UserDirs, history: &[String]) {
+    let Some(path) = history_path(dirs) else { return };
+    let Some(parent) = path.parent() else { return };
+    if fs::create_dir_all(parent).is_err() {
+        return;
+    }
+    let tmp = path.with_extension("tmp");
+    if fs::write(&tmp, history.join("\n") + "\n").is_ok() {
+        let _ = fs::rename(&tmp, &path);
+    }
+}
@@ -0,0 +1,252 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+
+/// Rebindable actions. The names are the keys of the `[keybindings]` table
+/// in `.finder.toml`:
+///
+/// | Name               | Default  | Where           |
+/// |--------------------|----------|-----------------|
+/// | `chat`             | `?`      | search          |
+/// | `quick_answer`     | `@`      | empty search    |
+/// | `directory_picker` | `ctrl+o` | search, chat    |
+/// | `citations`        | `alt+c`  | chat            |
+/// | `save_chat`        | `ctrl+s` | chat            |
+/// | `conversations`    | `alt+s`  | chat            |
+/// | `reload_key`       | `ctrl+k` | chat, quick     |
+/// | `chat_retrieval`   | `alt+r`  | chat            |
+/// | `extension_filter` | `ctrl+e` | search          |
+/// | `regex`            | `ctrl+r` | search          |
+/// | `file_scope`       | `ctrl+f` | search          |
+/// | `compare`          | `ctrl+d` | search          |
+/// | `history_prev`     | `ctrl+p` | search          |
+/// | `history_next`     | `ctrl+n` | search          |
+/// | `copy_match`       | `ctrl+t` | search          |
+/// | `copy_snippet`     | `ctrl+y` | search          |
+/// | `copy_location`    | `ctrl+l` | search          |
+/// | `open_in_place`    | `alt+enter` | search       |
+/// | `inline_context`   | `alt+i`  | search          |
+/// | `refresh`          | `f5`     | search          |
+/// | `wrap`             | `ctrl+w` | every mode      |
+/// | `help`             | `f1`     | every mode      |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Chat,
+    QuickAnswer,
+    DirectoryPicker,
+    Citations,
+    SaveChat,
+    Conversations,
+    ReloadKey,
+    ChatRetrieval,
+    ExtensionFilter,
+    Regex,
+    FileScope,
+    Compare,
+    HistoryPrev,
+    HistoryNext,
+    CopyMatch,
+    CopySnippet,
+    CopyLocation,
+    OpenInPlace,
+    InlineContext,
+    Refresh,
+    Wrap,
+    Help,
+}
+
+const DEFAULTS: [(Action, &str, &str); 22] = [
+    (Action::Chat, "chat", "?"),
+    (Action::QuickAnswer, "quick_answer", "@"),
+    (Action::DirectoryPicker, "directory_picker", "ctrl+o"),
+    (Action::Citations, "citations", "alt+c"),
+    (Action::SaveChat, "save_chat", "ctrl+s"),
+    (Action::Conversations, "conversations", "alt+s"),
+    (Action::ReloadKey, "reload_key", "ctrl+k"),
+    (Action::ChatRetrieval, "chat_retrieval", "alt+r"),
+    (Action::ExtensionFilter, "extension_filter", "ctrl+e"),
+    (Action::Regex, "regex", "ctrl+r"),
+    (Action::FileScope, "file_scope", "ctrl+f"),
+    (Action::Compare, "compare", "ctrl+d"),
+    (Action::HistoryPrev, "history_prev", "ctrl+p"),
+    (Action::HistoryNext, "history_next", "ctrl+n"),
+    (Action::CopyMatch, "copy_match", "ctrl+t"),
+    (Action::CopySnippet, "copy_snippet", "ctrl+y"),
+    (Action::CopyLocation, "copy_location", "ctrl+l"),
+    (Action::OpenInPlace, "open_in_place", "alt+enter"),
+    (Action::InlineContext, "inline_context", "alt+i"),
+    (Action::Refresh, "refresh", "f5"),
+    (Action::Wrap, "wrap", "ctrl+w"),
+    (Action::Help, "help", "f1"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    /// Parses `ctrl+o`, `alt+c`, `?`, `f5`, `pagedown` and the like.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim();
+        let (mods, key) = match spec.strip_suffix("++") {
+            Some(mods) => (mods, "+"),
+            None if spec == "+" => ("", "+"),
+            None => spec.rsplit_once('+').unwrap_or(("", spec)),
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for part in mods.split('+').filter(|p| !p.is_empty()) {
+            modifiers |= match part.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+        }
+
+        let code = match key.to_lowercase().as_str() {
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name if name.len() > 1 && name.starts_with('f') => KeyCode::F(name[1..].parse().ok()?),
+            _ => {
+                let mut chars = key.chars();
+                let c = chars.next()?;
+                if chars.next().is_some() {
+                    return None;
+                }
+                KeyCode::Char(if modifiers.is_empty() { c } else { c.to_ascii_lowercase() })
+            }
+        };
+
+        Some(Self { code, modifiers })
+    }
+
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        // Shift is implied by the character itself (`?`, `@`).
+        let ignore = match self.code {
+            KeyCode::Char(_) => KeyModifiers::SHIFT,
+            _ => KeyModifiers::NONE,
+        };
+        let code = match key.code {
+            KeyCode::Char(c) if !self.modifiers.is_empty() => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        code == self.code && key.modifiers - ignore == self.modifiers - ignore
+    }
+
+    /// Footer label, e.g. `Ctrl+O` or `Alt+c`.
+    pub fn label(&self) -> String {
+        let mut label = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            label.push_str("Ctrl+");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            label.push_str("Alt+");
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            label.push_str("Shift+");
+        }
+        match self.code {
+            KeyCode::Char(' ') => label.push_str("Space"),
+            KeyCode::Char(c) if self.modifiers.contains(KeyModifiers::CONTROL) => {
+                label.push(c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => label.push(c),
+            KeyCode::F(n) => label.push_str(&format!("F{}", n)),
+            code => label.push_str(&format!("{:?}", code)),
+        }
+        label
+    }
+}
+
+pub struct KeyMap {
+    bindings: Vec<(Action, KeyBinding)>,
+}
+
+impl KeyMap {
+    /// Defaults with `overrides` (action name to key) applied. Unknown
+    /// actions and unparsable keys are skipped and described in the
+    /// returned warnings, as are keys bound to two actions.
+    pub fn new(overrides: &HashMap<String, String>) -> (Self, Vec<String>) {
+        let mut bindings: Vec<(Action, KeyBinding)> = DEFAULTS
+            .iter()
+            .filter_map(|&(action, _, key)| Some((action, KeyBinding::parse(key)?)))
+            .collect();
+        let mut warnings = Vec::new();
+
+        let mut names: Vec<&String> = overrides.keys().collect();
+        names.sort();
+        for name in names {
+            let key = &overrides[name];
+            let Some(&(action, _, _)) = DEFAULTS.iter().find(|(_, n, _)| n == name) else {
+                warnings.push(format!("unknown key binding action `{}`", name));
+                continue;
+            };
+            let Some(binding) = KeyBinding::parse(key) else {
+                warnings.push(format!("invalid key `{}` for `{}`", key, name));
+                continue;
+            };
+            if let Some(slot) = bindings.iter_mut().find(|(a, _)| *a == action) {
+                slot.1 = binding;
+            }
+        }
+
+        // `action` picks the first binding that matches, so the earlier one wins.
+        let name = |action: Action| DEFAULTS.iter().find(|(a, _, _)| *a == action).map_or("", |(_, n, _)| n);
+        for (i, (action, binding)) in bindings.iter().enumerate() {
+            if let Some((first, _)) = bindings[..i].iter().find(|(_, b)| b == binding) {
+                warnings.push(format!(
+                    "`{}` and `{}` are both bound to {}, keeping it for `{}`",
+                    name(*first),
+                    name(*action),
+                    binding.label(),
+                    name(*first)
+                ));
+            }
+        }
+
+        (Self { bindings }, warnings)
+    }
+
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, binding)| binding.matches(key))
+            .map(|&(action, _)| action)
+    }
+
+    pub fn label(&self, action: Action) -> String {
+        self.bindings
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map(|(_, binding)| binding.label())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_keys_bound_twice() {
+        let (_, warnings) = KeyMap::new(&HashMap::new());
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        let overrides = HashMap::from([("regex".to_string(), "ctrl+o".to_string())]);
+        let (keymap, warnings) = KeyMap::new(&overrides);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`regex`"), "{}", warnings[0]);
+        assert!(warnings[0].contains("Ctrl+O"), "{}", warnings[0]);
+        assert_eq!(keymap.label(Action::Regex), "Ctrl+O");
+    }
+}
@@ -1,11 +1,25 @@
 mod app;
+#[cfg(feature = "archive")]
+mod archive;
 mod chat;
+mod clipboard;
 mod compass;
+mod config;
+mod conversations;
+mod editor;
+mod history;
+mod keymap;
 mod markdown;
+mod preview;
 mod rag;
 mod search;
+mod session;
+mod syntax;
+mod theme;
 mod ui;
 mod update;
+mod util;
+mod watch;
 
 use app::{App, Mode};
 use crossterm::{
@@ -13,39 +27,82 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use keymap::Action;
 use ratatui::prelude::*;
-use std::io::{self, stdout, Write};
-use std::process::Command;
+use std::io::{self, stdout};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use tokio::sync::mpsc;
-fn copy_to_clipboard(text: &str) {
-    use std::process::{Command, Stdio};
 
-    let clean_text: String = text
-        .chars()
-        .filter(|c| !matches!(*c, '│' | '┌' | '┐' | '└' | '┘' | '├' | '┤' | '┬' | '┴' | '┼' | '─' | '║' | '═'))
-        .collect();
+struct TerminalGuard;
 
-    let is_wsl = std::path::Path::new("/mnt/c/WINDOWS/system32/clip.exe").exists();
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        Ok(guard)
+    }
+}
 
-    let (cmd, args): (&str, &[&str]) = if is_wsl {
-        ("clip.exe", &[])
-    } else {
-        ("xclip", &["-selection", "clipboard"])
-    };
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
 
-    if let Ok(mut child) = Command::new(cmd)
-        .args(args)
-        .stdin(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()
-    {
-        if let Some(stdin) = child.stdin.as_mut() {
-            let _ = stdin.write_all(clean_text.as_bytes());
-        }
-        let _ = child.wait();
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(stdout(), LeaveAlternateScreen, DisableMouseCapture);
+}
+
+/// The steps of handing the terminal to the editor, so their order can be
+/// checked without a real terminal.
+trait Suspend {
+    fn restore(&mut self);
+    fn open_editor(&mut self, path: &std::path::Path, line: usize, column: Option<usize>) -> io::Result<()>;
+    fn reenter(&mut self) -> io::Result<()>;
+}
+
+impl<B: Backend> Suspend for Terminal<B> {
+    fn restore(&mut self) {
+        restore_terminal();
+    }
+
+    fn open_editor(&mut self, path: &std::path::Path, line: usize, column: Option<usize>) -> io::Result<()> {
+        editor::open(path, line, column)
+    }
+
+    fn reenter(&mut self) -> io::Result<()> {
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        self.clear()
     }
 }
 
+/// Hands the terminal to the editor and takes it back once the editor
+/// exits, whether or not it started. The outer error is the terminal's,
+/// the inner one the editor's.
+fn open_suspended(
+    terminal: &mut impl Suspend,
+    path: &std::path::Path,
+    line: usize,
+    column: Option<usize>,
+) -> io::Result<io::Result<()>> {
+    terminal.restore();
+    let result = terminal.open_editor(path, line, column);
+    terminal.reenter()?;
+    Ok(result)
+}
+
+// Release builds abort on panic, so Drop guards never run; restore from the hook instead.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_hook(info);
+    }));
+}
+
 fn main() -> io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
@@ -59,6 +116,64 @@ fn main() -> io::Result<()> {
         return Ok(());
     }
 
+    if args.contains(&"--list".to_string()) {
+        let cwd = std::env::current_dir()?;
+        list_files(&cwd, args.contains(&"--json".to_string()));
+        return Ok(());
+    }
+
+    if args.contains(&"--dump-index".to_string()) {
+        let cwd = std::env::current_dir()?;
+        dump_index(&cwd, args.contains(&"--json".to_string()));
+        return Ok(());
+    }
+
+    if let Some(pos) = args.iter().position(|a| a == "--ask") {
+        let Some(question) = args.get(pos + 1) else {
+            eprintln!("--ask expects a question");
+            std::process::exit(2);
+        };
+        if !ask(std::env::current_dir()?, question)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(pos) = args.iter().position(|a| a == "--query") {
+        let Some(query) = args.get(pos + 1) else {
+            eprintln!("--query expects a search query");
+            std::process::exit(2);
+        };
+        let limit = match args.iter().position(|a| a == "--limit") {
+            Some(pos) => match args.get(pos + 1).and_then(|n| n.parse().ok()) {
+                Some(limit) => limit,
+                None => {
+                    eprintln!("--limit expects a number");
+                    std::process::exit(2);
+                }
+            },
+            None => 20,
+        };
+        let cwd = std::env::current_dir()?;
+        run_query(&cwd, query, limit, args.contains(&"--json".to_string()));
+        return Ok(());
+    }
+
+    if let Some(pos) = args.iter().position(|a| a == "--index") {
+        let dir = match args.get(pos + 1).filter(|a| !a.starts_with("--")) {
+            Some(path) => std::fs::canonicalize(path)?,
+            None => std::env::current_dir()?,
+        };
+        build_index(&dir);
+        return Ok(());
+    }
+
+    if args.contains(&"--show-prompt".to_string()) {
+        let app = App::new(std::env::current_dir()?);
+        println!("{}", app.system_prompt());
+        return Ok(());
+    }
+
     let rt = tokio::runtime::Runtime::new().unwrap();
 
     let update_msg = rt.block_on(async {
@@ -73,28 +188,203 @@ fn main() -> io::Result<()> {
         );
     }
 
-    let cwd = std::env::current_dir()?;
+    let mut app = match args.iter().position(|a| a == "--archive") {
+        Some(pos) => {
+            let Some(path) = args.get(pos + 1) else {
+                eprintln!("--archive expects a path to a .zip or .tar(.gz) file");
+                std::process::exit(2);
+            };
+            open_archive(std::path::Path::new(path))?
+        }
+        None if args.contains(&"--last-dir".to_string()) => App::in_last_dir(std::env::current_dir()?),
+        None => App::new(std::env::current_dir()?),
+    };
+
+    if let Some(pos) = args.iter().position(|a| a == "--mode") {
+        match args.get(pos + 1).and_then(|m| Mode::from_name(m)) {
+            Some(mode) => app.mode = mode,
+            None => {
+                eprintln!("--mode expects one of: search, chat, quick");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    install_panic_hook();
+
+    loop {
+        let result = {
+            let _guard = TerminalGuard::enter()?;
+            let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+            rt.block_on(run(&mut terminal, &mut app))
+        };
+        let selected = app.selected_entry.take();
+        let edit_loop = app.config.edit_loop && result.is_ok() && selected.is_some();
+        if !edit_loop {
+            app.save_session();
+        }
+
+        if let Some(entry) = &selected {
+            editor::open(&app.resolve(&entry.file), entry.line_num, app.selected_column)?;
+        }
+
+        match selected {
+            Some(entry) if edit_loop => app.reload_after_edit(&entry),
+            _ => return result,
+        }
+    }
+}
+
+#[cfg(feature = "archive")]
+fn open_archive(path: &std::path::Path) -> io::Result<App> {
+    let files = archive::load_archive(path, &config::extensions(&std::env::current_dir()?))?;
+    let dir = archive::extract_dir(&std::fs::canonicalize(path)?)?;
+    archive::extract(&dir, &files)?;
+    Ok(App::from_archive(dir, files))
+}
+
+#[cfg(not(feature = "archive"))]
+fn open_archive(_path: &std::path::Path) -> io::Result<App> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "finder was built without archive support (rebuild with --features archive)",
+    ))
+}
+
+fn list_files(cwd: &std::path::Path, json: bool) {
+    let files = search::load_md_files(cwd, &config::extensions(cwd));
+
+    if json {
+        let items: Vec<serde_json::Value> = files
+            .iter()
+            .map(|f| serde_json::json!({ "file": f.name, "lines": f.content.lines().count() }))
+            .collect();
+        println!("{}", serde_json::Value::Array(items));
+    } else {
+        for file in &files {
+            println!("{}\t{}", file.name, file.content.lines().count());
+        }
+    }
+}
+
+/// Answers `question` like the quick-answer mode, streaming to stdout. The
+/// sources follow on stderr so piping the answer into `say` reads only the
+/// answer. False when no answer could be given.
+fn ask(cwd: std::path::PathBuf, question: &str) -> io::Result<bool> {
+    use std::io::Write;
+
     let mut app = App::new(cwd);
+    if app.api_key.is_none() {
+        eprintln!(
+            "{} not found. Set it in ~/.env or environment, or use a local model with FINDER_PROVIDER=ollama.",
+            app.provider.key_var().unwrap_or_default()
+        );
+        return Ok(false);
+    }
 
-    enable_raw_mode()?;
-    execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+    app.wait_for_rag_index();
+    app.quick_query = question.to_string();
+    app.prepare_quick_search();
+    let messages = app.build_quick_messages();
 
-    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let rt = tokio::runtime::Runtime::new()?;
+    let answered = rt.block_on(async {
+        spawn_chat(&app, messages, tx);
+
+        let mut out = stdout();
+        while let Some(chunk) = rx.recv().await {
+            if chunk == chat::DONE_SENTINEL {
+                return Ok(true);
+            }
+            if let Some(err) = chat::error_message(app.provider, &chunk) {
+                eprintln!("\nerror: {}", err);
+                return Ok(false);
+            }
+            app.chat_response.push_str(&chunk);
+            write!(out, "{}", chunk)?;
+            out.flush()?;
+        }
+        io::Result::Ok(false)
+    })?;
+
+    if answered {
+        println!();
+        // The citations the answer makes, as the citations panel shows them.
+        app.parse_citations();
+        if !app.citations.is_empty() {
+            eprintln!("\nSources:");
+            for citation in &app.citations {
+                let lines = if citation.end > citation.line {
+                    format!("{}-{}", citation.line, citation.end)
+                } else {
+                    citation.line.to_string()
+                };
+                let missing = if citation.valid { "" } else { " (not found)" };
+                eprintln!("  {}:{}{}", citation.file, lines, missing);
+            }
+        }
+    }
+    Ok(answered)
+}
 
-    let result = rt.block_on(run(&mut terminal, &mut app));
+fn run_query(cwd: &std::path::Path, query: &str, limit: usize, json: bool) {
+    let files = search::load_md_files(cwd, &config::extensions(cwd));
+    let config = config::Config::from_env();
+    let mut searcher = search::Searcher::from_files(&files, config.entry_mode, config.preserve_indent);
+    let results = searcher.search_complete(query);
+    let results = results.iter().take(limit);
+
+    if json {
+        let items: Vec<serde_json::Value> = results
+            .map(|e| {
+                serde_json::json!({
+                    "file": e.file,
+                    "line": e.line_num,
+                    "content": e.content,
+                    "score": search::Searcher::score(query, e),
+                    "rank": e.rank,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(items));
+    } else {
+        for entry in results {
+            println!("{}:{}\t{}", entry.file, entry.line_num, entry.content);
+        }
+    }
+}
 
-    disable_raw_mode()?;
-    execute!(stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+fn build_index(cwd: &std::path::Path) {
+    let files = search::load_md_files(cwd, &config::extensions(cwd));
+    let cache_dir = rag::get_cache_dir(&config::UserDirs::detect(), cwd, config::Config::from_env().sidecar_index);
+    let index = rag::RagIndex::new(&files, cwd, &cache_dir);
 
-    if let Some(entry) = app.selected_entry {
-        let file_path = app.cwd.join(&entry.file);
-        Command::new("nvim")
-            .arg(format!("+{}", entry.line_num))
-            .arg(&file_path)
-            .status()?;
+    println!("Indexed {} chunks from {} files", index.chunk_count(), files.len());
+    if index.is_cached() {
+        println!("Cache: {}", cache_dir.display());
+    } else {
+        println!("Cache directory unavailable, nothing was saved");
     }
+}
 
-    result
+fn dump_index(cwd: &std::path::Path, json: bool) {
+    let files = search::load_md_files(cwd, &config::extensions(cwd));
+    let cache_dir = rag::get_cache_dir(&config::UserDirs::detect(), cwd, config::Config::from_env().sidecar_index);
+    let index = rag::RagIndex::new(&files, cwd, &cache_dir);
+    let chunks = index.all_chunks();
+
+    if json {
+        let items: Vec<serde_json::Value> = chunks
+            .iter()
+            .map(|c| serde_json::json!({ "file": c.file, "line": c.line, "content": c.content }))
+            .collect();
+        println!("{}", serde_json::Value::Array(items));
+    } else {
+        for chunk in &chunks {
+            println!("{}:{}\t{}", chunk.file, chunk.line, chunk.content);
+        }
+    }
 }
 
 async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<()> {
@@ -108,23 +398,42 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
 
     loop {
         while let Ok(chunk) = rx.try_recv() {
-            app.append_response(&chunk);
+            app.queue_response(chunk);
         }
 
         while let Ok(chunk) = quick_rx.try_recv() {
-            app.append_quick_response(&chunk);
+            app.queue_quick_response(chunk);
+        }
+
+        app.flush_streams();
+        app.poll_search();
+        app.poll_rag_index();
+        app.poll_watcher();
+        app.poll_previews();
+        app.poll_directories();
+
+        app.autosave_if_idle();
+
+        if app.mode == Mode::Chat && app.chat_scroll > 0 && !app.show_prompt {
+            let size = terminal.size()?;
+            let max = ui::chat_max_scroll(Rect::new(0, 0, size.width, size.height), app);
+            app.chat_scroll = app.chat_scroll.min(max);
         }
 
         let completed = terminal.draw(|frame| {
             ui::draw(frame, app, selection_start, selection_end);
         })?;
 
+        let frame_area = completed.area;
         screen_buffer.clear();
         for y in 0..completed.area.height {
             let mut line = String::new();
-            for x in 0..completed.area.width {
-                let cell = &completed.buffer[(x, y)];
-                line.push_str(cell.symbol());
+            let mut x = 0;
+            while x < completed.area.width {
+                let symbol = completed.buffer[(x, y)].symbol();
+                line.push_str(symbol);
+                // The cell after a wide character is hidden behind it.
+                x += symbol.width().max(1) as u16;
             }
             screen_buffer.push(line);
         }
@@ -140,47 +449,124 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
                         continue;
                     }
 
+                    if app.context_menu.is_some() {
+                        match key.code {
+                            KeyCode::Esc => app.close_context_menu(),
+                            KeyCode::Up => app.context_menu_up(),
+                            KeyCode::Down => app.context_menu_down(),
+                            KeyCode::Enter => {
+                                if let Some(text) = app.apply_context_action() {
+                                    copy_to_clipboard(app, &text, "Copied");
+                                }
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+
+                    let action = app.keymap.action(&key);
+
+                    // Typing is debounced; every other key acts on the final query.
+                    if action.is_some() || !matches!(key.code, KeyCode::Char(_) | KeyCode::Backspace) {
+                        app.flush_search();
+                    }
+
+                    if action == Some(Action::Wrap) {
+                        app.toggle_wrap();
+                        continue;
+                    }
+
+                    if action == Some(Action::Help) {
+                        app.toggle_help();
+                        continue;
+                    }
+
                     match app.mode {
-                        Mode::Search => match key.code {
-                            KeyCode::Esc => app.on_escape(),
-                            KeyCode::Enter => app.on_enter(),
-                            KeyCode::Backspace => app.on_backspace(),
-                            KeyCode::Up => app.on_up(),
-                            KeyCode::Down => app.on_down(),
-                            KeyCode::Char(c) => {
-                                if key
-                                    .modifiers
-                                    .contains(crossterm::event::KeyModifiers::CONTROL)
-                                {
-                                    match c {
-                                        'c' => app.on_escape(),
-                                        'o' => app.enter_directory_picker(),
-                                        _ => {}
+                        Mode::Search => match action {
+                            Some(Action::Chat) => app.enter_chat(),
+                            Some(Action::QuickAnswer) if app.query.is_empty() => app.enter_quick_answer(),
+                            Some(Action::DirectoryPicker) => app.enter_directory_picker(),
+                            Some(Action::ExtensionFilter) => app.toggle_extension_filter(),
+                            Some(Action::Regex) => app.toggle_regex_mode(),
+                            Some(Action::FileScope) => app.toggle_file_scope(),
+                            Some(Action::Compare) => app.mark_for_compare(),
+                            Some(Action::HistoryPrev) => app.history_prev(),
+                            Some(Action::HistoryNext) => app.history_next(),
+                            Some(Action::CopyMatch) => {
+                                if let Some(text) = app.result_content(app.selected) {
+                                    copy_to_clipboard(app, &text, "Copied match");
+                                }
+                            }
+                            Some(Action::CopySnippet) => {
+                                if let Some(snippet) = app.preview_snippet() {
+                                    copy_to_clipboard(app, &snippet, "Copied snippet");
+                                }
+                            }
+                            Some(Action::CopyLocation) => {
+                                if let Some(location) = app.result_location() {
+                                    copy_to_clipboard(app, &location, format!("Copied {}", location));
+                                }
+                            }
+                            Some(Action::OpenInPlace) => {
+                                if let Some(entry) = app.entry_to_open() {
+                                    let path = app.resolve(&entry.file);
+                                    match open_suspended(terminal, &path, entry.line_num, app.selected_column)? {
+                                        Ok(()) => app.reload_after_edit(&entry),
+                                        Err(e) => {
+                                            app.status_message =
+                                                Some((format!("Could not start the editor: {}", e), std::time::Instant::now()));
+                                        }
                                     }
-                                } else {
-                                    app.on_char(c);
                                 }
                             }
-                            _ => {}
+                            Some(Action::InlineContext) => app.toggle_inline_context(),
+                            Some(Action::Refresh) => app.refresh_index(),
+                            _ => match key.code {
+                                KeyCode::Esc => app.on_escape(),
+                                KeyCode::Enter => app.on_enter(),
+                                KeyCode::Backspace => app.on_backspace(),
+                                KeyCode::Up => app.on_up(),
+                                KeyCode::Down => app.on_down(),
+                                KeyCode::Tab => app.toggle_result_expanded(),
+                                KeyCode::Left => app.preview_left(),
+                                KeyCode::Right => app.preview_right(),
+                                KeyCode::Char(c) => {
+                                    if key
+                                        .modifiers
+                                        .contains(crossterm::event::KeyModifiers::CONTROL)
+                                    {
+                                        if c == 'c' {
+                                            app.on_escape();
+                                        }
+                                    } else {
+                                        app.on_char(c);
+                                    }
+                                }
+                                _ => {}
+                            },
                         },
                         Mode::Chat => match key.code {
+                            _ if action == Some(Action::DirectoryPicker) && !app.chat_streaming => {
+                                app.enter_directory_picker();
+                            }
+                            _ if action == Some(Action::Citations) && !app.citations.is_empty() => {
+                                app.enter_citations_mode();
+                            }
+                            _ if action == Some(Action::SaveChat) && !app.chat_streaming => app.save_conversation(),
+                            _ if action == Some(Action::ChatRetrieval) => app.toggle_chat_retrieval(),
+                            _ if action == Some(Action::ReloadKey) && !app.chat_streaming => app.reload_api_key(),
+                            _ if action == Some(Action::Conversations) && !app.chat_streaming => {
+                                app.enter_conversations();
+                            }
                             KeyCode::Esc if !app.chat_streaming => app.on_escape(),
-                            KeyCode::Enter => {
+                            KeyCode::Enter
                                 if !app.chat_streaming
                                     && !app.chat_input.is_empty()
-                                    && app.api_key.is_some()
-                                {
-                                    let messages = app.build_messages();
-                                    let api_key = app.api_key.clone().unwrap();
-                                    let new_tx = tx.clone();
-
-                                    app.start_chat();
-
-                                    tokio::spawn(async move {
-                                        let _ =
-                                            chat::stream_chat(&api_key, messages, new_tx).await;
-                                    });
-                                }
+                                    && app.api_key.is_some() =>
+                            {
+                                let messages = app.build_messages();
+                                app.start_chat();
+                                spawn_chat(app, messages, tx.clone());
                             }
                             KeyCode::Char(c)
                                 if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) =>
@@ -193,18 +579,17 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
                                             app.on_escape();
                                         }
                                     }
-                                    'o' if !app.chat_streaming => app.enter_directory_picker(),
+                                    'l' if !app.chat_streaming => app.recall_last_question(),
+                                    'p' => app.toggle_prompt_view(),
                                     _ => {}
                                 }
                             }
                             KeyCode::Backspace if !app.chat_streaming => app.on_backspace(),
                             KeyCode::Up => app.on_up(),
                             KeyCode::Down => app.on_down(),
-                            KeyCode::Char('c')
-                                if key.modifiers.contains(crossterm::event::KeyModifiers::ALT)
-                                    && !app.citations.is_empty() =>
-                            {
-                                app.enter_citations_mode();
+                            KeyCode::Char(c @ ('[' | ']')) if app.chat_input.is_empty() => {
+                                let rows = ui::chat_heading_rows(frame_area, app);
+                                app.jump_to_heading(&rows, c == ']');
                             }
                             KeyCode::Char(c) if !app.chat_streaming => {
                                 app.on_char(c);
@@ -213,6 +598,12 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
                         },
                         Mode::Citations => match key.code {
                             KeyCode::Esc => app.on_escape(),
+                            KeyCode::PageUp | KeyCode::PageDown => {
+                                let visible = ui::citations_preview_height(frame_area);
+                                let step = (visible / 2).max(1) as isize;
+                                let delta = if key.code == KeyCode::PageUp { -step } else { step };
+                                app.scroll_citation_preview(delta, visible);
+                            }
                             KeyCode::Enter => {
                                 app.jump_to_citation(app.citations_selected);
                             }
@@ -232,6 +623,42 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
                             }
                             _ => {}
                         },
+                        Mode::Conversations => match key.code {
+                            KeyCode::Esc => app.on_escape(),
+                            KeyCode::Enter => app.open_conversation(),
+                            KeyCode::Up => app.on_up(),
+                            KeyCode::Down => app.on_down(),
+                            KeyCode::Char('c')
+                                if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) =>
+                            {
+                                app.on_escape();
+                            }
+                            _ => {}
+                        },
+                        Mode::Help => match key.code {
+                            KeyCode::Esc => app.on_escape(),
+                            KeyCode::Up => app.on_up(),
+                            KeyCode::Down => app.on_down(),
+                            KeyCode::Char('c')
+                                if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) =>
+                            {
+                                app.on_escape();
+                            }
+                            _ => {}
+                        },
+                        Mode::Compare => match key.code {
+                            KeyCode::Esc => app.on_escape(),
+                            KeyCode::Up => app.on_up(),
+                            KeyCode::Down => app.on_down(),
+                            KeyCode::PageUp => app.scroll_compare(-10),
+                            KeyCode::PageDown => app.scroll_compare(10),
+                            KeyCode::Char('c')
+                                if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) =>
+                            {
+                                app.on_escape();
+                            }
+                            _ => {}
+                        },
                         Mode::DirectoryPicker => match key.code {
                             KeyCode::Esc => app.on_escape(),
                             KeyCode::Enter => app.select_directory(),
@@ -251,29 +678,28 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
                             }
                             _ => {}
                         },
+                        Mode::QuickAnswer if app.confirm_rebuild_pending => {
+                            app.resolve_rag_rebuild(matches!(key.code, KeyCode::Char('y' | 'Y')));
+                        }
                         Mode::QuickAnswer => match key.code {
+                            _ if action == Some(Action::ReloadKey) && !app.quick_streaming => app.reload_api_key(),
                             KeyCode::Esc if !app.quick_streaming => app.on_escape(),
                             KeyCode::Tab => app.toggle_quick_sources(),
                             KeyCode::Up if app.quick_sources_expanded => app.quick_sources_up(),
                             KeyCode::Down if app.quick_sources_expanded => app.quick_sources_down(),
+                            KeyCode::Up => app.on_up(),
+                            KeyCode::Down => app.on_down(),
                             KeyCode::Enter => {
                                 if app.quick_sources_expanded && !app.quick_sources.is_empty() {
-                                    app.open_quick_source();
+                                    open_quick_source(terminal, app)?;
                                 } else if !app.quick_streaming
                                     && !app.quick_query.is_empty()
                                     && app.api_key.is_some()
                                 {
                                     app.prepare_quick_search();
                                     let messages = app.build_quick_messages();
-                                    let api_key = app.api_key.clone().unwrap();
-                                    let new_tx = quick_tx.clone();
-
                                     app.start_quick_answer();
-
-                                    tokio::spawn(async move {
-                                        let _ =
-                                            chat::stream_chat(&api_key, messages, new_tx).await;
-                                    });
+                                    spawn_chat(app, messages, quick_tx.clone());
                                 }
                             }
                             KeyCode::Char(c)
@@ -288,11 +714,17 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
                                         }
                                     }
                                     'r' if !app.quick_streaming => {
-                                        app.rebuild_rag_index();
+                                        app.request_rag_rebuild();
                                     }
                                     'n' if !app.quick_streaming => {
                                         app.new_quick_conversation();
                                     }
+                                    'f' if app.escalate_to_chat() => {
+                                        let messages = app.build_messages();
+                                        app.start_chat();
+                                        spawn_chat(app, messages, tx.clone());
+                                    }
+                                    'p' => app.toggle_prompt_view(),
                                     _ => {}
                                 }
                             }
@@ -302,33 +734,89 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
                         },
                     }
                 }
+                // Redraw at the new size right away rather than on the next event.
+                Event::Resize(..) => terminal.autoresize()?,
                 Event::Mouse(mouse) => {
+                    app.flush_search();
                     match mouse.kind {
+                        MouseEventKind::Down(MouseButton::Left) if app.context_menu.is_some() => {
+                            let item = app.context_menu.as_ref().and_then(|menu| {
+                                ui::context_menu_item_at(frame_area, menu, mouse.column, mouse.row)
+                            });
+                            match item {
+                                Some(idx) => {
+                                    if let Some(menu) = app.context_menu.as_mut() {
+                                        menu.selected = idx;
+                                    }
+                                    if let Some(text) = app.apply_context_action() {
+                                        copy_to_clipboard(app, &text, "Copied");
+                                    }
+                                }
+                                None => app.close_context_menu(),
+                            }
+                        }
+                        MouseEventKind::Down(MouseButton::Right) if app.mode == Mode::Search => {
+                            match ui::result_at(frame_area, app, mouse.column, mouse.row) {
+                                Some(idx) => app.open_context_menu(idx, (mouse.column, mouse.row)),
+                                None => app.close_context_menu(),
+                            }
+                        }
+                        MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                            let down = mouse.kind == MouseEventKind::ScrollDown;
+                            if app.mode == Mode::Citations && ui::in_citations_preview(frame_area, mouse.column, mouse.row) {
+                                let visible = ui::citations_preview_height(frame_area);
+                                let step = app::WHEEL_STEP as isize;
+                                app.scroll_citation_preview(if down { step } else { -step }, visible);
+                            } else if app.mode == Mode::Search && ui::in_search_preview(frame_area, app, mouse.column, mouse.row) {
+                                let visible = ui::search_preview_height(frame_area, app);
+                                let step = app::WHEEL_STEP as isize;
+                                app.scroll_preview(if down { step } else { -step }, visible);
+                            } else {
+                                app.on_scroll(down);
+                            }
+                        }
                         MouseEventKind::Down(MouseButton::Left) => {
                             selection_start = Some((mouse.column, mouse.row));
                             selection_end = Some((mouse.column, mouse.row));
                             selecting = true;
                         }
-                        MouseEventKind::Drag(MouseButton::Left) => {
-                            if selecting {
-                                selection_end = Some((mouse.column, mouse.row));
-                            }
+                        MouseEventKind::Drag(MouseButton::Left) if selecting => {
+                            selection_end = Some((mouse.column, mouse.row));
                         }
-                        MouseEventKind::Up(MouseButton::Left) => {
-                            if selecting {
-                                selection_end = Some((mouse.column, mouse.row));
-                                selecting = false;
-
-                                if let (Some(start), Some(end)) = (selection_start, selection_end) {
-                                    let text = extract_text(&screen_buffer, start, end);
-                                    if !text.is_empty() {
-                                        copy_to_clipboard(&text);
+                        MouseEventKind::Up(MouseButton::Left) if selecting => {
+                            selection_end = Some((mouse.column, mouse.row));
+                            selecting = false;
+
+                            let click = match (selection_start, selection_end) {
+                                (Some(start), Some(end)) if start == end => Some(start),
+                                _ => None,
+                            };
+                            let link = click
+                                .filter(|_| app.mode == Mode::Chat)
+                                .and_then(|(col, row)| app.link_at(col, row));
+                            let item = click.and_then(|(col, row)| ui::list_item_at(frame_area, app, col, row));
+
+                            if let Some(url) = link {
+                                app.open_link(&url);
+                            } else if let Some(idx) = item {
+                                // A click selects; clicking the selected row opens it.
+                                if app.select_list_item(idx) {
+                                    match app.mode {
+                                        Mode::Citations => app.jump_to_citation(idx),
+                                        Mode::DirectoryPicker => app.select_directory(),
+                                        Mode::QuickAnswer => open_quick_source(terminal, app)?,
+                                        _ => {}
                                     }
                                 }
-
-                                selection_start = None;
-                                selection_end = None;
+                            } else if let (Some(start), Some(end)) = (selection_start, selection_end) {
+                                let text = extract_text(&screen_buffer, start, end);
+                                if !text.is_empty() {
+                                    copy_to_clipboard(app, &text, "Copied selection");
+                                }
                             }
+
+                            selection_start = None;
+                            selection_end = None;
                         }
                         _ => {}
                     }
@@ -339,6 +827,48 @@ async fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Resul
     }
 }
 
+/// Streams the answer to `messages` from the app's provider and model into
+/// `tx` on a background task. Does nothing without an API key.
+fn spawn_chat(app: &App, messages: Vec<chat::ChatMessage>, tx: mpsc::UnboundedSender<String>) {
+    let Some(api_key) = app.api_key.clone() else { return };
+    let (provider, model) = (app.provider, app.model.clone());
+    tokio::spawn(async move {
+        let _ = chat::stream_chat(provider, &api_key, &model, messages, tx).await;
+    });
+}
+
+fn open_quick_source(terminal: &mut impl Suspend, app: &mut App) -> io::Result<()> {
+    if let Some((path, line)) = app.quick_source_to_open() {
+        if let Err(e) = open_suspended(terminal, &path, line, None)? {
+            app.status_message = Some((format!("Could not start the editor: {}", e), std::time::Instant::now()));
+        }
+    }
+    Ok(())
+}
+
+/// Index of the character drawn at terminal column `col`, counting wide
+/// characters as two columns and combining marks as none.
+fn char_at_column(chars: &[char], col: usize) -> usize {
+    let mut used = 0;
+    for (i, c) in chars.iter().enumerate() {
+        used += c.width().unwrap_or(0);
+        if used > col {
+            return i;
+        }
+    }
+    chars.len()
+}
+
+/// Copies `text` and reports in the status line whether it worked.
+fn copy_to_clipboard(app: &mut App, text: &str, copied: impl Into<String>) {
+    let message = if clipboard::copy(text) {
+        copied.into()
+    } else {
+        "Could not copy: no clipboard tool worked (install wl-copy, xclip or xsel)".to_string()
+    };
+    app.status_message = Some((message, std::time::Instant::now()));
+}
+
 fn extract_text(buffer: &[String], start: (u16, u16), end: (u16, u16)) -> String {
     let (start, end) = if start.1 < end.1 || (start.1 == end.1 && start.0 <= end.0) {
         (start, end)
@@ -359,8 +889,8 @@ fn extract_text(buffer: &[String], start: (u16, u16), end: (u16, u16)) -> String
         let line = &buffer[row];
         let chars: Vec<char> = line.chars().collect();
 
-        let col_start = if row == start_row { start_col } else { 0 };
-        let col_end = if row == end_row { (end_col + 1).min(chars.len()) } else { chars.len() };
+        let col_start = if row == start_row { char_at_column(&chars, start_col) } else { 0 };
+        let col_end = if row == end_row { char_at_column(&chars, end_col + 1) } else { chars.len() };
 
         if col_start < chars.len() {
             let selected: String = chars[col_start..col_end.min(chars.len())].iter().collect();
@@ -374,3 +904,71 @@ fn extract_text(buffer: &[String], start: (u16, u16), end: (u16, u16)) -> String
 
     result.trim().to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Records each step instead of touching the terminal.
+    struct Recorder {
+        steps: Vec<&'static str>,
+        editor_fails: bool,
+    }
+
+    impl Suspend for Recorder {
+        fn restore(&mut self) {
+            self.steps.push("restore");
+        }
+
+        fn open_editor(&mut self, _path: &Path, _line: usize, _column: Option<usize>) -> io::Result<()> {
+            self.steps.push("open");
+            if self.editor_fails {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "nvim not found"));
+            }
+            Ok(())
+        }
+
+        fn reenter(&mut self) -> io::Result<()> {
+            self.steps.push("reenter");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn open_suspended_restores_then_opens_then_reenters() {
+        let mut terminal = Recorder { steps: Vec::new(), editor_fails: false };
+        assert!(open_suspended(&mut terminal, Path::new("a.md"), 1, None).unwrap().is_ok());
+        assert_eq!(terminal.steps, ["restore", "open", "reenter"]);
+    }
+
+    #[test]
+    fn open_suspended_reenters_when_the_editor_fails() {
+        let mut terminal = Recorder { steps: Vec::new(), editor_fails: true };
+        assert!(open_suspended(&mut terminal, Path::new("a.md"), 1, None).unwrap().is_err());
+        assert_eq!(terminal.steps, ["restore", "open", "reenter"]);
+    }
+
+    #[test]
+    fn open_quick_source_reports_a_failed_editor() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = tempfile::tempdir().unwrap();
+        let mut app = App::with_dirs(dir.path().to_path_buf(), config::UserDirs::under(state.path()));
+        app.config.open_in_split = false;
+        app.quick_sources = vec![rag::RagChunk {
+            file: "notes.md".to_string(),
+            line: 3,
+            content: String::new(),
+            snippet: String::new(),
+            highlights: Vec::new(),
+            score: 0.0,
+        }];
+        let mut terminal = Recorder { steps: Vec::new(), editor_fails: true };
+
+        open_quick_source(&mut terminal, &mut app).unwrap();
+
+        assert_eq!(terminal.steps, ["restore", "open", "reenter"]);
+        let (message, _) = app.status_message.unwrap();
+        assert!(message.starts_with("Could not start the editor"), "{}", message);
+    }
+}
@@ -1,23 +1,51 @@
-use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd, HeadingLevel, CodeBlockKind};
+use crate::theme::Theme;
+use pulldown_cmark::{Alignment, Event, Options, Parser, Tag, TagEnd, HeadingLevel, CodeBlockKind};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use ratatui::{
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
     text::{Line, Span, Text},
 };
+use std::ops::Range;
 
-const DIM: Color = Color::Rgb(140, 140, 140);
-const YELLOW: Color = Color::Rgb(255, 200, 100);
-const CODE_BG: Color = Color::Rgb(30, 30, 35);
-const CODE_FG: Color = Color::Rgb(180, 180, 180);
 
-pub fn render(input: &str) -> Text<'static> {
+/// Renders markdown into styled lines. `width` is only used to fit tables;
+/// everything else is left for the paragraph to wrap.
+pub fn render(input: &str, width: usize, theme: &Theme) -> Text<'static> {
+    render_with_headings(input, width, theme).0
+}
+
+/// Like `render`, also returning the index of each heading's line.
+pub fn render_with_headings(input: &str, width: usize, theme: &Theme) -> (Text<'static>, Vec<usize>) {
+    let mut renderer = rendered(input, width, theme);
+    let headings = std::mem::take(&mut renderer.headings);
+    (renderer.into_text(), headings)
+}
+
+/// Like `render`, also returning where each link's text was rendered.
+pub fn render_with_links(input: &str, width: usize, theme: &Theme) -> (Text<'static>, Vec<LinkSpan>) {
+    let mut renderer = rendered(input, width, theme);
+    let links = std::mem::take(&mut renderer.links);
+    (renderer.into_text(), links)
+}
+
+fn rendered(input: &str, width: usize, theme: &Theme) -> MarkdownRenderer {
     let mut opts = Options::empty();
     opts.insert(Options::ENABLE_STRIKETHROUGH);
     opts.insert(Options::ENABLE_TASKLISTS);
+    opts.insert(Options::ENABLE_TABLES);
 
     let parser = Parser::new_ext(input, opts);
-    let mut renderer = MarkdownRenderer::new();
+    let mut renderer = MarkdownRenderer::new(width, *theme);
     renderer.run(parser);
-    renderer.into_text()
+    renderer
+}
+
+/// A rendered link: the characters of one line its text covers. A link
+/// broken over several lines gets one span per line.
+pub struct LinkSpan {
+    pub line: usize,
+    pub chars: Range<usize>,
+    pub url: String,
 }
 
 struct MarkdownRenderer {
@@ -29,19 +57,38 @@ struct MarkdownRenderer {
     code_block_lang: String,
     needs_newline: bool,
     blockquote_depth: usize,
+    width: usize,
+    table: Option<Table>,
+    headings: Vec<usize>,
+    links: Vec<LinkSpan>,
+    /// The open link's URL and the line and character it starts at.
+    link: Option<(String, usize, usize)>,
+    theme: Theme,
+}
+
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+    cell: String,
 }
 
 impl MarkdownRenderer {
-    fn new() -> Self {
+    fn new(width: usize, theme: Theme) -> Self {
         Self {
             lines: Vec::new(),
             current_spans: Vec::new(),
-            style_stack: vec![Style::default().fg(Color::White)],
+            style_stack: vec![Style::default().fg(theme.text)],
             list_stack: Vec::new(),
             in_code_block: false,
             code_block_lang: String::new(),
             needs_newline: false,
             blockquote_depth: 0,
+            width,
+            table: None,
+            headings: Vec::new(),
+            links: Vec::new(),
+            link: None,
+            theme,
         }
     }
 
@@ -77,7 +124,18 @@ impl MarkdownRenderer {
             Tag::Emphasis => self.push_style(Style::default().add_modifier(Modifier::ITALIC)),
             Tag::Strong => self.push_style(Style::default().add_modifier(Modifier::BOLD)),
             Tag::Strikethrough => self.push_style(Style::default().add_modifier(Modifier::CROSSED_OUT)),
-            Tag::Link { .. } => self.push_style(Style::default().add_modifier(Modifier::UNDERLINED)),
+            Tag::Link { dest_url, .. } => {
+                if self.table.is_none() {
+                    self.link = Some((dest_url.to_string(), self.lines.len(), self.current_chars()));
+                }
+                self.push_style(Style::default().add_modifier(Modifier::UNDERLINED));
+            }
+            Tag::Table(alignments) => self.start_table(alignments),
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = self.table.as_mut() {
+                    table.rows.push(Vec::new());
+                }
+            }
             _ => {}
         }
     }
@@ -90,7 +148,20 @@ impl MarkdownRenderer {
             TagEnd::CodeBlock => self.end_code_block(),
             TagEnd::List(_) => self.end_list(),
             TagEnd::Item => {}
-            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link => {
+            TagEnd::Table => self.end_table(),
+            TagEnd::TableCell => {
+                if let Some(table) = self.table.as_mut() {
+                    let cell = std::mem::take(&mut table.cell);
+                    if let Some(row) = table.rows.last_mut() {
+                        row.push(cell.trim().to_string());
+                    }
+                }
+            }
+            TagEnd::Link => {
+                self.end_link();
+                self.pop_style();
+            }
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => {
                 self.pop_style();
             }
             _ => {}
@@ -117,19 +188,20 @@ impl MarkdownRenderer {
 
         let style = match level {
             HeadingLevel::H1 => Style::default()
-                .fg(Color::White)
+                .fg(self.theme.text)
                 .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
             HeadingLevel::H2 => Style::default()
-                .fg(Color::White)
+                .fg(self.theme.text)
                 .add_modifier(Modifier::BOLD),
             HeadingLevel::H3 => Style::default()
-                .fg(Color::White)
+                .fg(self.theme.text)
                 .add_modifier(Modifier::BOLD | Modifier::ITALIC),
             _ => Style::default()
-                .fg(Color::White)
+                .fg(self.theme.text)
                 .add_modifier(Modifier::ITALIC),
         };
 
+        self.headings.push(self.lines.len());
         self.push_style(style);
         self.needs_newline = false;
     }
@@ -146,7 +218,7 @@ impl MarkdownRenderer {
             self.push_line(Line::default());
         }
         self.blockquote_depth += 1;
-        self.push_style(Style::default().fg(DIM).add_modifier(Modifier::ITALIC));
+        self.push_style(Style::default().fg(self.theme.dim).add_modifier(Modifier::ITALIC));
         self.needs_newline = false;
     }
 
@@ -167,13 +239,13 @@ impl MarkdownRenderer {
         };
 
         let header = format!("```{}", self.code_block_lang);
-        self.push_line(Line::styled(header, Style::default().fg(CODE_FG).bg(CODE_BG)));
+        self.push_line(Line::styled(header, Style::default().fg(self.theme.code_fg).bg(self.theme.code_bg)));
         self.in_code_block = true;
         self.needs_newline = false;
     }
 
     fn end_code_block(&mut self) {
-        self.push_line(Line::styled("```", Style::default().fg(CODE_FG).bg(CODE_BG)));
+        self.push_line(Line::styled("```", Style::default().fg(self.theme.code_fg).bg(self.theme.code_bg)));
         self.in_code_block = false;
         self.needs_newline = true;
     }
@@ -201,14 +273,14 @@ impl MarkdownRenderer {
                 None => {
                     let bullet = Span::styled(
                         format!("{}• ", indent),
-                        Style::default().fg(Color::White),
+                        Style::default().fg(self.theme.text),
                     );
                     self.current_spans.push(bullet);
                 }
                 Some(num) => {
                     let number = Span::styled(
                         format!("{}{}. ", indent, num),
-                        Style::default().fg(Color::White),
+                        Style::default().fg(self.theme.text),
                     );
                     *num += 1;
                     self.current_spans.push(number);
@@ -222,17 +294,84 @@ impl MarkdownRenderer {
         let marker = if checked { "[x] " } else { "[ ] " };
         self.current_spans.push(Span::styled(
             marker.to_string(),
-            Style::default().fg(if checked { Color::White } else { DIM }),
+            Style::default().fg(if checked { self.theme.text } else { self.theme.dim }),
         ));
     }
 
+    fn start_table(&mut self, alignments: Vec<Alignment>) {
+        self.flush_line();
+        if !self.lines.is_empty() {
+            self.push_line(Line::default());
+        }
+        self.table = Some(Table { alignments, rows: Vec::new(), cell: String::new() });
+        self.needs_newline = false;
+    }
+
+    fn end_table(&mut self) {
+        let Some(table) = self.table.take() else { return };
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+
+        let mut widths = vec![0; columns];
+        for row in &table.rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.width());
+            }
+        }
+
+        // Shrink the widest column until the table fits, keeping room for " │ ".
+        let available = self.width.saturating_sub(3 * (columns - 1));
+        while self.width > 0 && widths.iter().sum::<usize>() > available {
+            let Some(widest) = widths.iter_mut().max() else { break };
+            if *widest <= 3 {
+                break;
+            }
+            *widest -= 1;
+        }
+
+        let border = Style::default().fg(self.theme.dim);
+        for (r, row) in table.rows.iter().enumerate() {
+            let cell_style = if r == 0 {
+                Style::default().fg(self.theme.text).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(self.theme.text)
+            };
+            let mut spans = Vec::new();
+            for (i, &width) in widths.iter().enumerate() {
+                if i > 0 {
+                    spans.push(Span::styled(" │ ", border));
+                }
+                let cell = row.get(i).map(String::as_str).unwrap_or("");
+                let align = table.alignments.get(i).copied().unwrap_or(Alignment::None);
+                spans.push(Span::styled(fit_cell(cell, width, align), cell_style));
+            }
+            self.push_line(Line::from(spans));
+
+            if r == 0 {
+                let rule: Vec<String> = widths.iter().map(|&w| "─".repeat(w)).collect();
+                self.push_line(Line::styled(rule.join("─┼─"), border));
+            }
+        }
+        self.needs_newline = true;
+    }
+
     fn text(&mut self, text: &str) {
+        if let Some(table) = self.table.as_mut() {
+            table.cell.push_str(text);
+            return;
+        }
+
         if self.in_code_block {
+            let base = Style::default().fg(self.theme.code_fg).bg(self.theme.code_bg);
             for line in text.lines() {
-                self.push_line(Line::styled(
-                    format!("  {}", line),
-                    Style::default().fg(CODE_FG).bg(CODE_BG),
-                ));
+                let mut spans = vec![Span::styled("  ", base)];
+                match crate::syntax::highlight_line(line, &self.code_block_lang, base, &self.theme.syntax) {
+                    Some(tokens) => spans.extend(tokens),
+                    None => spans.push(Span::styled(line.to_string(), base)),
+                }
+                self.push_line(Line::from(spans).style(base));
             }
             return;
         }
@@ -248,7 +387,7 @@ impl MarkdownRenderer {
                 if self.current_spans.is_empty() || i > 0 {
                     self.current_spans.push(Span::styled(
                         prefix.clone(),
-                        Style::default().fg(DIM),
+                        Style::default().fg(self.theme.dim),
                     ));
                 }
                 self.current_spans.push(Span::styled(line.to_string(), style));
@@ -259,9 +398,13 @@ impl MarkdownRenderer {
     }
 
     fn inline_code(&mut self, code: &str) {
+        if let Some(table) = self.table.as_mut() {
+            table.cell.push_str(code);
+            return;
+        }
         self.current_spans.push(Span::styled(
             format!(" {} ", code),
-            Style::default().fg(CODE_FG).bg(CODE_BG),
+            Style::default().fg(self.theme.code_fg).bg(self.theme.code_bg),
         ));
     }
 
@@ -280,7 +423,7 @@ impl MarkdownRenderer {
         }
         self.push_line(Line::styled(
             "─".repeat(40),
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(self.theme.dim),
         ));
         self.needs_newline = true;
     }
@@ -297,6 +440,27 @@ impl MarkdownRenderer {
         }
     }
 
+    /// Characters in the line being built.
+    fn current_chars(&self) -> usize {
+        self.current_spans.iter().map(|s| s.content.chars().count()).sum()
+    }
+
+    fn end_link(&mut self) {
+        let Some((url, start_line, start)) = self.link.take() else { return };
+        let end_line = self.lines.len();
+        for line in start_line..=end_line {
+            let from = if line == start_line { start } else { 0 };
+            let to = if line == end_line {
+                self.current_chars()
+            } else {
+                self.lines.get(line).map_or(0, |l| l.spans.iter().map(|s| s.content.chars().count()).sum())
+            };
+            if from < to {
+                self.links.push(LinkSpan { line, chars: from..to, url: url.clone() });
+            }
+        }
+    }
+
     fn current_style(&self) -> Style {
         *self.style_stack.last().unwrap_or(&Style::default())
     }
@@ -319,7 +483,32 @@ impl MarkdownRenderer {
     }
 }
 
-pub fn highlight_citations(text: Text<'static>) -> Text<'static> {
+fn fit_cell(cell: &str, width: usize, align: Alignment) -> String {
+    let mut text = String::new();
+    if cell.width() > width {
+        let mut used = 0;
+        for c in cell.chars() {
+            let w = c.width().unwrap_or(0);
+            if used + w + 1 > width {
+                break;
+            }
+            text.push(c);
+            used += w;
+        }
+        text.push('…');
+    } else {
+        text.push_str(cell);
+    }
+
+    let pad = width.saturating_sub(text.width());
+    match align {
+        Alignment::Right => format!("{}{}", " ".repeat(pad), text),
+        Alignment::Center => format!("{}{}{}", " ".repeat(pad / 2), text, " ".repeat(pad - pad / 2)),
+        Alignment::Left | Alignment::None => format!("{}{}", text, " ".repeat(pad)),
+    }
+}
+
+pub fn highlight_citations(text: Text<'static>, theme: &Theme) -> Text<'static> {
     let citation_re = regex::Regex::new(r"\[([^\]]+:\d+(?:[-,]\s*\d+)*)\]").unwrap();
 
     let new_lines: Vec<Line<'static>> = text
@@ -353,7 +542,7 @@ pub fn highlight_citations(text: Text<'static>) -> Text<'static> {
 
                 new_spans.push(Span::styled(
                     m.as_str().to_string(),
-                    Style::default().fg(YELLOW).add_modifier(Modifier::DIM),
+                    Style::default().fg(theme.highlight).add_modifier(Modifier::DIM),
                 ));
 
                 last_end = m.end();
@@ -0,0 +1,121 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Files kept in memory, most recently requested first.
+const CAPACITY: usize = 16;
+/// How long a cached file is trusted before its mtime is checked again.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the preview panes can show for a file.
+pub enum Preview<'a> {
+    Loading,
+    Unreadable,
+    Ready(&'a str),
+}
+
+struct Entry {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// `None` when the file could not be read.
+    content: Option<String>,
+    checked: Instant,
+}
+
+struct Loaded {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// `None` when the mtime still matches the cached copy.
+    content: Option<Option<String>>,
+}
+
+/// File contents for the preview panes, read on background threads so a
+/// slow filesystem never blocks drawing. Cached copies are re-read only
+/// when the file's mtime changes.
+pub struct PreviewCache {
+    entries: VecDeque<Entry>,
+    pending: HashSet<PathBuf>,
+    tx: Sender<Loaded>,
+    rx: Receiver<Loaded>,
+}
+
+impl PreviewCache {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { entries: VecDeque::new(), pending: HashSet::new(), tx, rx }
+    }
+
+    /// Starts loading `path` unless it is cached and was checked recently.
+    pub fn request(&mut self, path: &Path) {
+        if self.pending.contains(path) {
+            return;
+        }
+        let known = match self.entries.iter().position(|e| e.path == path) {
+            Some(idx) => {
+                let entry = self.entries.remove(idx).unwrap();
+                let fresh = entry.checked.elapsed() < RECHECK_INTERVAL;
+                let modified = entry.modified;
+                self.entries.push_front(entry);
+                if fresh {
+                    return;
+                }
+                Some(modified)
+            }
+            None => None,
+        };
+
+        self.pending.insert(path.to_path_buf());
+        let tx = self.tx.clone();
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            let content = if known == Some(modified) && modified.is_some() {
+                None
+            } else {
+                Some(std::fs::read_to_string(&path).ok())
+            };
+            let _ = tx.send(Loaded { path, modified, content });
+        });
+    }
+
+    /// Stores finished reads. Returns true when any cached content changed.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for loaded in self.rx.try_iter() {
+            self.pending.remove(&loaded.path);
+            let checked = Instant::now();
+            match self.entries.iter_mut().find(|e| e.path == loaded.path) {
+                Some(entry) => {
+                    entry.checked = checked;
+                    entry.modified = loaded.modified;
+                    if let Some(content) = loaded.content {
+                        entry.content = content;
+                        changed = true;
+                    }
+                }
+                // Evicted while its recheck ran; it is read again when next requested.
+                None if loaded.content.is_none() => {}
+                None => {
+                    self.entries.push_front(Entry {
+                        path: loaded.path,
+                        modified: loaded.modified,
+                        content: loaded.content.flatten(),
+                        checked,
+                    });
+                    changed = true;
+                }
+            }
+        }
+        self.entries.truncate(CAPACITY);
+        changed
+    }
+
+    pub fn get(&self, path: &Path) -> Preview<'_> {
+        match self.entries.iter().find(|e| e.path == path) {
+            Some(Entry { content: Some(content), .. }) => Preview::Ready(content),
+            Some(_) => Preview::Unreadable,
+            None => Preview::Loading,
+        }
+    }
+}
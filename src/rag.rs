@@ -1,18 +1,24 @@
+use crate::config::UserDirs;
 use crate::search::LoadedFile;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
+use tantivy::query::{AllQuery, BooleanQuery, FuzzyTermQuery, Query, QueryParser};
 use tantivy::schema::{Schema, Field, TEXT, STORED, STRING, Value};
-use tantivy::{doc, Index, IndexWriter, IndexSettings, ReloadPolicy, directory::MmapDirectory};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{doc, Index, Term, IndexWriter, IndexSettings, ReloadPolicy, directory::MmapDirectory};
 
 #[derive(Debug, Clone)]
 pub struct RagChunk {
     pub file: String,
     pub line: usize,
     pub content: String,
-    #[allow(dead_code)]
+    /// Short excerpt around the matched terms, empty when nothing matched
+    /// (e.g. fuzzy hits or `all_chunks`).
+    pub snippet: String,
+    /// Byte ranges of the matched terms within `snippet`.
+    pub highlights: Vec<std::ops::Range<usize>>,
     pub score: f32,
 }
 
@@ -21,16 +27,38 @@ pub struct RagIndex {
     file_field: Field,
     line_field: Field,
     content_field: Field,
+    /// False when the index lives in memory because the cache directory
+    /// could not be used.
+    cached: bool,
 }
 
-fn get_cache_dir(cwd: &std::path::Path) -> PathBuf {
+pub const SIDECAR_DIR: &str = ".finder-index";
+const MIN_STRICT_CHUNKS: usize = 3;
+const SNIPPET_CHARS: usize = 120;
+/// Bumped whenever chunking changes, so older caches get rebuilt.
+const INDEX_FORMAT: u32 = 2;
+
+pub fn get_cache_dir(dirs: &UserDirs, cwd: &std::path::Path, prefer_sidecar: bool) -> PathBuf {
+    let sidecar = cwd.join(SIDECAR_DIR);
+    if prefer_sidecar || sidecar.join("meta.json").exists() {
+        return sidecar;
+    }
+
     let hash = format!("{:x}", md5::compute(cwd.to_string_lossy().as_bytes()));
-    dirs::cache_dir()
-        .unwrap_or_else(|| PathBuf::from("/tmp"))
-        .join("finder")
+    dirs.cache
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("/tmp").join("finder"))
         .join(&hash[..16])
 }
 
+pub fn clear_cache(dirs: &UserDirs, cwd: &std::path::Path) {
+    if let Some(cache_dir) = &dirs.cache {
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+    // Keep a shared sidecar in place; dropping its mtimes forces a rebuild.
+    let _ = fs::remove_file(cwd.join(SIDECAR_DIR).join("mtimes.json"));
+}
+
 fn get_file_mtimes(files: &[LoadedFile], cwd: &std::path::Path) -> HashMap<String, u64> {
     files.iter().filter_map(|f| {
         let path = cwd.join(&f.name);
@@ -42,24 +70,34 @@ fn get_file_mtimes(files: &[LoadedFile], cwd: &std::path::Path) -> HashMap<Strin
     }).collect()
 }
 
-fn load_cached_mtimes(cache_dir: &PathBuf) -> Option<HashMap<String, u64>> {
+fn load_cached_mtimes(cache_dir: &Path) -> Option<HashMap<String, u64>> {
+    let format = fs::read_to_string(cache_dir.join("format")).ok()?;
+    if format.trim() != INDEX_FORMAT.to_string() {
+        return None;
+    }
     let path = cache_dir.join("mtimes.json");
     let content = fs::read_to_string(path).ok()?;
     serde_json::from_str(&content).ok()
 }
 
-fn save_mtimes(cache_dir: &PathBuf, mtimes: &HashMap<String, u64>) {
+fn save_mtimes(cache_dir: &Path, mtimes: &HashMap<String, u64>) {
     let path = cache_dir.join("mtimes.json");
     if let Ok(json) = serde_json::to_string(mtimes) {
         let _ = fs::write(path, json);
+        let _ = fs::write(cache_dir.join("format"), INDEX_FORMAT.to_string());
     }
 }
 
+/// Splits a file into sections at each heading. Every section's content is
+/// prefixed with the headings it is nested under (`Guide > Setup > ...`) so
+/// retrieval matches on section titles too; its line is the heading's line.
 fn extract_sections(content: &str) -> Vec<(usize, String)> {
     let mut sections = Vec::new();
     let mut current_section = String::new();
     let mut start_line = 0;
     let mut in_code_block = false;
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut breadcrumb = String::new();
 
     for (i, line) in content.lines().enumerate() {
         if line.trim().starts_with("```") {
@@ -69,11 +107,18 @@ fn extract_sections(content: &str) -> Vec<(usize, String)> {
         let is_header = !in_code_block && line.starts_with('#');
 
         if is_header && !current_section.is_empty() {
-            sections.push((start_line + 1, current_section.trim().to_string()));
+            sections.push((start_line + 1, with_breadcrumb(&breadcrumb, &current_section)));
             current_section.clear();
             start_line = i;
         }
 
+        if is_header {
+            let level = line.chars().take_while(|&c| c == '#').count();
+            headings.retain(|(l, _)| *l < level);
+            breadcrumb = headings.iter().map(|(_, t)| t.as_str()).collect::<Vec<_>>().join(" > ");
+            headings.push((level, line.trim_start_matches('#').trim().to_string()));
+        }
+
         if current_section.is_empty() && !line.trim().is_empty() {
             start_line = i;
         }
@@ -87,12 +132,20 @@ fn extract_sections(content: &str) -> Vec<(usize, String)> {
     }
 
     if !current_section.is_empty() {
-        sections.push((start_line + 1, current_section.trim().to_string()));
+        sections.push((start_line + 1, with_breadcrumb(&breadcrumb, &current_section)));
     }
 
     sections
 }
 
+fn with_breadcrumb(breadcrumb: &str, section: &str) -> String {
+    if breadcrumb.is_empty() {
+        section.trim().to_string()
+    } else {
+        format!("{} > {}", breadcrumb, section.trim())
+    }
+}
+
 fn build_schema() -> (Schema, Field, Field, Field) {
     let mut schema_builder = Schema::builder();
     let file_field = schema_builder.add_text_field("file", STRING | STORED);
@@ -101,74 +154,242 @@ fn build_schema() -> (Schema, Field, Field, Field) {
     (schema_builder.build(), file_field, line_field, content_field)
 }
 
+/// True when the cached index is missing or older than the files, meaning
+/// `RagIndex::new` will have to (re)index files.
+pub fn needs_rebuild(files: &[LoadedFile], cwd: &std::path::Path, cache_dir: &Path) -> bool {
+    load_cached_mtimes(cache_dir).as_ref() != Some(&get_file_mtimes(files, cwd))
+        || !cache_dir.join("meta.json").exists()
+}
+
 impl RagIndex {
-    pub fn new(files: &[LoadedFile], cwd: &std::path::Path) -> Self {
-        let cache_dir = get_cache_dir(cwd);
-        let current_mtimes = get_file_mtimes(files, cwd);
-        let cached_mtimes = load_cached_mtimes(&cache_dir);
+    /// Opens the index, updating it first if files changed. Only changed,
+    /// added and removed files are re-indexed when a cache already exists.
+    /// A cache that cannot be opened or written is rebuilt from scratch, and
+    /// when that fails too the index is kept in memory for this session.
+    /// `cache_dir` comes from `get_cache_dir`.
+    pub fn new(files: &[LoadedFile], cwd: &std::path::Path, cache_dir: &Path) -> Self {
+        match Self::open_and_update(files, cwd, cache_dir, true) {
+            Ok(rag) => rag,
+            // Another finder is updating this cache; answer from it as it is.
+            Err(tantivy::TantivyError::LockFailure(..)) => {
+                Self::open_cached(cache_dir).unwrap_or_else(|| Self::in_memory(files))
+            }
+            Err(_) => Self::open_and_update(files, cwd, cache_dir, false)
+                .unwrap_or_else(|_| Self::in_memory(files)),
+        }
+    }
 
-        let needs_rebuild = cached_mtimes.as_ref() != Some(&current_mtimes)
-            || !cache_dir.join("meta.json").exists();
+    fn open_and_update(files: &[LoadedFile], cwd: &Path, cache_dir: &Path, reuse: bool) -> tantivy::Result<Self> {
+        let current_mtimes = get_file_mtimes(files, cwd);
+        let cached_mtimes = load_cached_mtimes(cache_dir).filter(|_| reuse);
 
         let (schema, file_field, line_field, content_field) = build_schema();
 
-        let index = if needs_rebuild {
-            let _ = fs::remove_dir_all(&cache_dir);
-            fs::create_dir_all(&cache_dir).unwrap();
-
-            let dir = MmapDirectory::open(&cache_dir).unwrap();
-            let index = Index::create(dir, schema, IndexSettings::default()).unwrap();
-            let mut index_writer: IndexWriter = index.writer(15_000_000).unwrap();
-
-            for file in files {
-                for (line_num, section) in extract_sections(&file.content) {
-                    index_writer.add_document(doc!(
-                        file_field => file.name.clone(),
-                        line_field => line_num.to_string(),
-                        content_field => section
-                    )).unwrap();
-                }
+        let existing = (reuse && cache_dir.join("meta.json").exists())
+            .then(|| MmapDirectory::open(cache_dir).ok().and_then(|dir| Index::open(dir).ok()))
+            .flatten();
+
+        let (index, stale) = match (existing, cached_mtimes) {
+            (Some(index), Some(cached)) => {
+                let stale: HashSet<String> = cached
+                    .keys()
+                    .chain(current_mtimes.keys())
+                    .filter(|name| cached.get(*name) != current_mtimes.get(*name))
+                    .cloned()
+                    .collect();
+                (index, Some(stale))
+            }
+            _ => {
+                let _ = fs::remove_dir_all(cache_dir);
+                fs::create_dir_all(cache_dir)?;
+                let dir = MmapDirectory::open(cache_dir)?;
+                (Index::create(dir, schema, IndexSettings::default())?, None)
             }
-            index_writer.commit().unwrap();
-            save_mtimes(&cache_dir, &current_mtimes);
-            index
-        } else {
-            let dir = MmapDirectory::open(&cache_dir).unwrap();
-            Index::open(dir).unwrap()
         };
 
-        Self { index, file_field, line_field, content_field }
+        let rag = Self { index, file_field, line_field, content_field, cached: true };
+        if stale.as_ref().is_some_and(|s| s.is_empty()) {
+            return Ok(rag);
+        }
+
+        rag.index_files(files, stale.as_ref())?;
+        save_mtimes(cache_dir, &current_mtimes);
+        Ok(rag)
+    }
+
+    /// An index that lives only as long as this session, for when the cache
+    /// directory is unusable. Empty if even that fails.
+    fn in_memory(files: &[LoadedFile]) -> Self {
+        let (schema, file_field, line_field, content_field) = build_schema();
+        let rag = Self { index: Index::create_in_ram(schema), file_field, line_field, content_field, cached: false };
+        let _ = rag.index_files(files, None);
+        rag
+    }
+
+    /// Indexes `files`, or only the `stale` ones after deleting their old
+    /// sections.
+    fn index_files(&self, files: &[LoadedFile], stale: Option<&HashSet<String>>) -> tantivy::Result<()> {
+        let mut index_writer: IndexWriter = self.index.writer(15_000_000)?;
+        if let Some(stale) = stale {
+            for name in stale {
+                index_writer.delete_term(Term::from_field_text(self.file_field, name));
+            }
+        }
+
+        for file in files {
+            if stale.is_some_and(|s| !s.contains(&file.name)) {
+                continue;
+            }
+            for (line_num, section) in extract_sections(&file.content) {
+                index_writer.add_document(doc!(
+                    self.file_field => file.name.clone(),
+                    self.line_field => line_num.to_string(),
+                    self.content_field => section
+                ))?;
+            }
+        }
+        index_writer.commit()?;
+        Ok(())
+    }
+
+    /// Opens whatever index is on disk without checking it against the
+    /// files, to answer from while `new` brings it up to date.
+    pub fn open_cached(cache_dir: &Path) -> Option<Self> {
+        if !cache_dir.join("meta.json").exists() {
+            return None;
+        }
+        let index = Index::open(MmapDirectory::open(cache_dir).ok()?).ok()?;
+        let (_, file_field, line_field, content_field) = build_schema();
+        Some(Self { index, file_field, line_field, content_field, cached: true })
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.cached
+    }
+
+    /// Best-scoring chunks for `query`, highest first. Chunks scoring below
+    /// `min_relative` times the top score are dropped.
+    pub fn search_chunks(&self, query: &str, limit: usize, min_relative: f32) -> Vec<RagChunk> {
+        let query_parser = QueryParser::for_index(&self.index, vec![self.content_field]);
+
+        match query_parser.parse_query(query) {
+            Ok(q) => self.run_query(q.as_ref(), limit, min_relative),
+            Err(_) => Vec::new(),
+        }
     }
 
-    pub fn search_chunks(&self, query: &str, limit: usize) -> Vec<RagChunk> {
-        let reader = self.index
+    /// Like `search_chunks`, but tops up terse or misspelled queries with a
+    /// fuzzy match on each term, listed after the exact matches. The flag is
+    /// true when the fallback was used.
+    pub fn search_chunks_relaxed(&self, query: &str, limit: usize, min_relative: f32) -> (Vec<RagChunk>, bool) {
+        let mut chunks = self.search_chunks(query, limit, min_relative);
+        if chunks.len() >= MIN_STRICT_CHUNKS {
+            return (chunks, false);
+        }
+
+        let terms: Vec<Box<dyn Query>> = query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| t.chars().count() >= 3)
+            .map(|t| {
+                let term = Term::from_field_text(self.content_field, &t.to_lowercase());
+                Box::new(FuzzyTermQuery::new_prefix(term, 1, true)) as Box<dyn Query>
+            })
+            .collect();
+        if terms.is_empty() {
+            return (chunks, false);
+        }
+
+        let before = chunks.len();
+        for chunk in self.run_query(&BooleanQuery::union(terms), limit, min_relative) {
+            if chunks.len() >= limit {
+                break;
+            }
+            if !chunks.iter().any(|c| c.file == chunk.file && c.line == chunk.line) {
+                chunks.push(chunk);
+            }
+        }
+        let relaxed = chunks.len() > before;
+        (chunks, relaxed)
+    }
+
+    fn run_query(&self, query: &dyn Query, limit: usize, min_relative: f32) -> Vec<RagChunk> {
+        let Ok(reader) = self.index
             .reader_builder()
             .reload_policy(ReloadPolicy::Manual)
             .try_into()
-            .unwrap();
-        let searcher = reader.searcher();
-        let query_parser = QueryParser::for_index(&self.index, vec![self.content_field]);
-
-        let parsed_query = match query_parser.parse_query(query) {
-            Ok(q) => q,
-            Err(_) => return Vec::new(),
+        else {
+            return Vec::new();
         };
+        let searcher = reader.searcher();
 
-        let top_docs = match searcher.search(&parsed_query, &TopDocs::with_limit(limit)) {
+        let mut top_docs = match searcher.search(query, &TopDocs::with_limit(limit)) {
             Ok(docs) => docs,
             Err(_) => return Vec::new(),
         };
+        top_docs.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let cutoff = top_docs.first().map_or(0.0, |(top, _)| top * min_relative);
+        top_docs.retain(|(score, _)| *score >= cutoff);
+
+        let snippets = SnippetGenerator::create(&searcher, query, self.content_field)
+            .ok()
+            .map(|mut generator| {
+                generator.set_max_num_chars(SNIPPET_CHARS);
+                generator
+            });
 
         let mut chunks = Vec::new();
         for (score, doc_address) in top_docs {
             if let Ok(doc) = searcher.doc::<tantivy::TantivyDocument>(doc_address) {
-                let file = doc.get_first(self.file_field).and_then(|v| v.as_str()).unwrap_or("").to_string();
-                let line = doc.get_first(self.line_field).and_then(|v| v.as_str()).unwrap_or("0").parse().unwrap_or(0);
-                let content = doc.get_first(self.content_field).and_then(|v| v.as_str()).unwrap_or("").to_string();
-                chunks.push(RagChunk { file, line, content, score });
+                chunks.push(self.to_chunk(&doc, score, snippets.as_ref()));
             }
         }
         chunks
     }
 
+    pub fn chunk_count(&self) -> u64 {
+        self.index.reader().map_or(0, |reader| reader.searcher().num_docs())
+    }
+
+    /// Every chunk stored in the index, ordered by file and line.
+    pub fn all_chunks(&self) -> Vec<RagChunk> {
+        let Ok(reader) = self.index.reader() else { return Vec::new() };
+        let searcher = reader.searcher();
+        let total = searcher.num_docs() as usize;
+        if total == 0 {
+            return Vec::new();
+        }
+
+        let Ok(top_docs) = searcher.search(&AllQuery, &TopDocs::with_limit(total)) else {
+            return Vec::new();
+        };
+
+        let mut chunks: Vec<RagChunk> = top_docs
+            .into_iter()
+            .filter_map(|(score, addr)| {
+                let doc = searcher.doc::<tantivy::TantivyDocument>(addr).ok()?;
+                Some(self.to_chunk(&doc, score, None))
+            })
+            .collect();
+        chunks.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+        chunks
+    }
+
+    fn to_chunk(
+        &self,
+        doc: &tantivy::TantivyDocument,
+        score: f32,
+        snippets: Option<&SnippetGenerator>,
+    ) -> RagChunk {
+        let file = doc.get_first(self.file_field).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let line = doc.get_first(self.line_field).and_then(|v| v.as_str()).unwrap_or("0").parse().unwrap_or(0);
+        let content = doc.get_first(self.content_field).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let (snippet, highlights) = match snippets.map(|g| g.snippet(&content)) {
+            Some(snippet) if !snippet.is_empty() => {
+                (snippet.fragment().to_string(), snippet.highlighted().to_vec())
+            }
+            _ => (String::new(), Vec::new()),
+        };
+        RagChunk { file, line, content, snippet, highlights, score }
+    }
+
 }
@@ -1,9 +1,16 @@
+use crate::rag::SIDECAR_DIR;
 use ignore::WalkBuilder;
 use nucleo::{Config, Nucleo, Utf32String};
 use nucleo_matcher::{Matcher, pattern::Pattern, pattern::CaseMatching, pattern::Normalization};
-use std::path::Path;
+use regex::Regex;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// Most results a search returns.
+pub const MAX_RESULTS: usize = 100;
+
 #[derive(Clone)]
 pub struct LoadedFile {
     pub name: String,
@@ -16,34 +23,93 @@ pub struct SearchEntry {
     pub line_num: usize,
     pub content: String,
     pub match_indices: Vec<u32>,
+    pub match_groups: Vec<u8>,
+    /// Position in nucleo's ranking, 1 for the best match; 0 for regex
+    /// matches and entries that were not ranked.
+    pub rank: u32,
+    pub lang: Option<String>,
 }
 
-pub fn load_md_files(dir: &Path) -> Vec<LoadedFile> {
-    let mut files = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryMode {
+    Line,
+    Section,
+}
+
+/// Ignore file read in every directory on top of `.gitignore` and `.ignore`.
+pub const IGNORE_FILE: &str = ".finderignore";
 
-    let walker = WalkBuilder::new(dir)
+/// Walker shared by indexing and the directory picker, so both skip the
+/// same things: `.gitignore` (in or out of a git repository, plus the
+/// global one and `.git/info/exclude`), `.ignore` and `.finderignore`, each
+/// overriding the one before and all supporting `!` negations. Hidden files
+/// are included; `.git` and the sidecar index directory are not.
+pub fn walker(dir: &Path) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(dir);
+    builder
         .hidden(false)
         .git_ignore(true)
-        .build();
+        .require_git(false)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .filter_entry(|entry| entry.file_name() != ".git" && entry.file_name() != SIDECAR_DIR);
+    builder
+}
 
-    for result in walker {
-        let Ok(entry) = result else { continue };
-        let path = entry.path();
+/// Files under `dir` with one of `extensions`, honoring the ignore files.
+pub fn tracked_paths(dir: &Path, extensions: &[String]) -> Vec<PathBuf> {
+    tracked(walker(dir), extensions)
+}
 
-        if !path.is_file() {
-            continue;
-        }
+/// Whether `walker` reaches `path`, file or directory, decided by walking
+/// only its parent. Ignored directories further up are not checked.
+pub fn is_walked(path: &Path) -> bool {
+    let Some(parent) = path.parent() else { return false };
+    let mut builder = walker(parent);
+    builder.max_depth(Some(1));
+    builder.build().filter_map(|result| result.ok()).any(|entry| entry.path() == path)
+}
+
+/// Whether `tracked_paths` would list the file at `path`, decided by
+/// walking only its own directory.
+pub fn is_tracked(path: &Path, extensions: &[String]) -> bool {
+    let Some(parent) = path.parent() else { return false };
+    let mut builder = walker(parent);
+    builder.max_depth(Some(1));
+    tracked(builder, extensions).iter().any(|p| p == path)
+}
+
+fn tracked(builder: WalkBuilder, extensions: &[String]) -> Vec<PathBuf> {
+    builder
+        .build()
+        .filter_map(|result| result.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| extensions.contains(&ext.to_string_lossy().to_lowercase()))
+        })
+        .collect()
+}
+
+pub fn load_md_files(dir: &Path, extensions: &[String]) -> Vec<LoadedFile> {
+    load_files(dir, tracked_paths(dir, extensions))
+}
 
-        let Some(ext) = path.extension() else { continue };
-        if ext != "md" {
+/// Reads `paths`, naming each relative to `dir`. Unreadable and binary
+/// files are skipped.
+pub fn load_files(dir: &Path, paths: Vec<PathBuf>) -> Vec<LoadedFile> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        // Invalid UTF-8 fails the read; NUL bytes catch the binaries that don't.
+        let Ok(content) = std::fs::read_to_string(&path) else { continue };
+        if content.contains('\0') {
             continue;
         }
 
-        let Ok(content) = std::fs::read_to_string(path) else { continue };
-
         let name = path
             .strip_prefix(dir)
-            .unwrap_or(path)
+            .unwrap_or(&path)
             .to_string_lossy()
             .to_string();
 
@@ -54,24 +120,107 @@ pub fn load_md_files(dir: &Path) -> Vec<LoadedFile> {
 }
 
 pub fn build_context(files: &[LoadedFile]) -> String {
-    let mut context = String::new();
-    for file in files {
-        context.push_str(&format!("\n--- {} ---\n", file.name));
-        for (i, line) in file.content.lines().enumerate() {
-            context.push_str(&format!("[{}:{}] {}\n", file.name, i + 1, line));
-        }
+    files.iter().map(file_context).collect()
+}
+
+/// One file's part of the chat context, every line prefixed with `[file:line]`.
+pub fn file_context(file: &LoadedFile) -> String {
+    let mut context = format!("\n--- {} ---\n", file.name);
+    for (i, line) in file.content.lines().enumerate() {
+        context.push_str(&format!("[{}:{}] {}\n", file.name, i + 1, line));
     }
     context
 }
 
+fn fence_language(fence: &str) -> Option<String> {
+    let lang = fence.trim_start_matches('`').split_whitespace().next()?;
+    Some(lang.to_lowercase())
+}
+
+/// `lang:` and `file:` terms pulled out of a query.
+#[derive(Default)]
+struct QueryFilters {
+    lang: Option<String>,
+    file: Option<String>,
+}
+
+fn split_filters(query: &str) -> (QueryFilters, String) {
+    let mut filters = QueryFilters::default();
+    let mut rest = Vec::new();
+    for term in query.split_whitespace() {
+        if let Some(l) = term.strip_prefix("lang:").filter(|l| !l.is_empty()) {
+            filters.lang = Some(l.to_lowercase());
+        } else if let Some(f) = term.strip_prefix("file:").filter(|f| !f.is_empty()) {
+            filters.file = Some(f.to_string());
+        } else {
+            rest.push(term);
+        }
+    }
+    (filters, rest.join(" "))
+}
+
+fn frontmatter_aliases(content: &str) -> (Vec<(usize, String)>, Range<usize>) {
+    let mut aliases = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    if lines.next().map(|(_, l)| l.trim()) != Some("---") {
+        return (aliases, 0..0);
+    }
+
+    let mut block = 0..0;
+    let mut in_block = false;
+
+    for (i, line) in lines {
+        if line.trim() == "---" {
+            break;
+        }
+
+        if in_block {
+            if let Some(item) = line.trim_start().strip_prefix('-') {
+                push_alias(&mut aliases, i, item);
+                block.end = i + 1;
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) {
+                continue;
+            }
+            in_block = false;
+        }
+
+        let Some(value) = line.strip_prefix("aliases:").or_else(|| line.strip_prefix("alias:")) else {
+            continue;
+        };
+
+        block = i..i + 1;
+        in_block = true;
+        let value = value.trim();
+        if let Some(list) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            for item in list.split(',') {
+                push_alias(&mut aliases, i, item);
+            }
+        } else {
+            push_alias(&mut aliases, i, value);
+        }
+    }
+
+    (aliases, block)
+}
+
+fn push_alias(aliases: &mut Vec<(usize, String)>, line_idx: usize, raw: &str) {
+    let alias = raw.trim().trim_matches('"').trim_matches('\'').trim();
+    if !alias.is_empty() {
+        aliases.push((line_idx, alias.to_string()));
+    }
+}
+
 pub struct Searcher {
     entries: Vec<SearchEntry>,
     nucleo: Nucleo<u32>,
 }
 
 impl Searcher {
-    pub fn from_files(files: &[LoadedFile]) -> Self {
-        let entries = Self::build_entries(files);
+    pub fn from_files(files: &[LoadedFile], mode: EntryMode, preserve_indent: bool) -> Self {
+        let entries = Self::build_entries(files, mode, preserve_indent);
         let config = Config::DEFAULT.match_paths();
         let nucleo: Nucleo<u32> = Nucleo::new(config, Arc::new(|| {}), None, 1);
 
@@ -86,29 +235,150 @@ impl Searcher {
         Self { entries, nucleo }
     }
 
-    fn build_entries(files: &[LoadedFile]) -> Vec<SearchEntry> {
+    fn build_entries(files: &[LoadedFile], mode: EntryMode, preserve_indent: bool) -> Vec<SearchEntry> {
         let mut entries = Vec::new();
 
         for file in files {
-            for (line_idx, line) in file.content.lines().enumerate() {
-                let trimmed = line.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
-
+            let (aliases, alias_lines) = frontmatter_aliases(&file.content);
+            for (line_idx, alias) in aliases {
                 entries.push(SearchEntry {
                     file: file.name.clone(),
                     line_num: line_idx + 1,
-                    content: trimmed.to_string(),
+                    content: format!("alias: {}", alias),
                     match_indices: Vec::new(),
+                    match_groups: Vec::new(),
+                    rank: 0,
+                    lang: None,
                 });
             }
+
+            match mode {
+                EntryMode::Line => Self::push_line_entries(&mut entries, file, &alias_lines, preserve_indent),
+                EntryMode::Section => Self::push_section_entries(&mut entries, file, &alias_lines),
+            }
         }
 
         entries
     }
 
-    pub fn search(&mut self, query: &str) -> Vec<SearchEntry> {
+    fn push_line_entries(entries: &mut Vec<SearchEntry>, file: &LoadedFile, skip: &Range<usize>, preserve_indent: bool) {
+        let mut fence_lang: Option<Option<String>> = None;
+
+        for (line_idx, line) in file.content.lines().enumerate() {
+            if skip.contains(&line_idx) {
+                continue;
+            }
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            let is_fence = trimmed.starts_with("```");
+            if is_fence {
+                fence_lang = match fence_lang {
+                    Some(_) => None,
+                    None => Some(fence_language(trimmed)),
+                };
+            }
+
+            entries.push(SearchEntry {
+                file: file.name.clone(),
+                line_num: line_idx + 1,
+                content: if preserve_indent { line.trim_end() } else { trimmed }.to_string(),
+                match_indices: Vec::new(),
+                match_groups: Vec::new(),
+                rank: 0,
+                lang: if is_fence { None } else { fence_lang.clone().flatten() },
+            });
+        }
+    }
+
+    fn push_section_entries(entries: &mut Vec<SearchEntry>, file: &LoadedFile, skip: &Range<usize>) {
+        let mut flush = |start: usize, parts: &mut Vec<&str>, lang: &mut Option<String>| {
+            if !parts.is_empty() {
+                entries.push(SearchEntry {
+                    file: file.name.clone(),
+                    line_num: start + 1,
+                    content: parts.join(" "),
+                    match_indices: Vec::new(),
+                    match_groups: Vec::new(),
+                    rank: 0,
+                    lang: lang.take(),
+                });
+                parts.clear();
+            }
+            *lang = None;
+        };
+
+        let mut start = 0;
+        let mut parts: Vec<&str> = Vec::new();
+        let mut lang: Option<String> = None;
+        let mut in_code_block = false;
+
+        for (line_idx, line) in file.content.lines().enumerate() {
+            if skip.contains(&line_idx) {
+                continue;
+            }
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") {
+                if !in_code_block && lang.is_none() {
+                    lang = fence_language(trimmed);
+                }
+                in_code_block = !in_code_block;
+            }
+
+            if !in_code_block && line.starts_with('#') {
+                flush(start, &mut parts, &mut lang);
+                start = line_idx;
+                let title = trimmed.trim_start_matches('#').trim();
+                if !title.is_empty() {
+                    parts.push(title);
+                }
+                continue;
+            }
+
+            if trimmed.is_empty() {
+                continue;
+            }
+            if parts.is_empty() {
+                start = line_idx;
+            }
+            parts.push(trimmed);
+        }
+
+        flush(start, &mut parts, &mut lang);
+    }
+
+    /// Fuzzy search over `"{file} {content}"`. Nucleo treats each
+    /// space-separated term as its own atom and requires all of them, in any
+    /// order; highlights are the union of every term's matches in the line.
+    /// Returns what nucleo ranked within 100 ms, so typing stays responsive.
+    pub fn search(&mut self, query: &str, file: Option<&str>) -> Vec<SearchEntry> {
+        self.search_inner(query, file, false)
+    }
+
+    /// Like `search`, but waits until nucleo has matched every entry.
+    pub fn search_complete(&mut self, query: &str) -> Vec<SearchEntry> {
+        self.search_inner(query, None, true)
+    }
+
+    /// Nucleo's score for `entry` against `query`, the number its ranking
+    /// orders by (higher is better); 0 when the entry doesn't match.
+    pub fn score(query: &str, entry: &SearchEntry) -> u32 {
+        let (_, query) = split_filters(query);
+        let pattern = Pattern::parse(&query, CaseMatching::Ignore, Normalization::Smart);
+        let mut matcher = Matcher::new(nucleo_matcher::Config::DEFAULT.match_paths());
+        let mut buf = Vec::new();
+        let haystack = format!("{} {}", entry.file, entry.content);
+        pattern.score(nucleo_matcher::Utf32Str::new(&haystack, &mut buf), &mut matcher).unwrap_or(0)
+    }
+
+    fn search_inner(&mut self, query: &str, file: Option<&str>, complete: bool) -> Vec<SearchEntry> {
+        let (filters, query) = split_filters(query);
+        let query = query.as_str();
+        let lang = filters.lang;
+        let files = filters.file.map(|f| self.files_matching(&f));
+
         self.nucleo.pattern.reparse(
             0,
             query,
@@ -117,30 +387,135 @@ impl Searcher {
             false,
         );
 
-        self.nucleo.tick(100);
+        let mut status = self.nucleo.tick(100);
+        while complete && status.running {
+            status = self.nucleo.tick(100);
+        }
 
         let snapshot = self.nucleo.snapshot();
         let mut results = Vec::new();
         let mut matcher = Matcher::new(nucleo_matcher::Config::DEFAULT);
-        let pattern = Pattern::parse(query, CaseMatching::Ignore, Normalization::Smart);
+        let term_patterns: Vec<Pattern> = query
+            .split_whitespace()
+            .map(|term| Pattern::parse(term, CaseMatching::Ignore, Normalization::Smart))
+            .collect();
 
-        for item in snapshot.matched_items(..snapshot.matched_item_count().min(100)) {
-            let idx = *item.data as usize;
-            if idx < self.entries.len() {
-                let mut entry = self.entries[idx].clone();
-                let mut indices = Vec::new();
-                let mut buf = Vec::new();
-                let haystack = nucleo_matcher::Utf32Str::new(&entry.content, &mut buf);
-                pattern.indices(haystack, &mut matcher, &mut indices);
-                entry.match_indices = indices;
-                results.push(entry);
-            }
+        let matching = snapshot
+            .matched_items(..)
+            .zip(1..)
+            .map(|(item, rank)| (*item.data as usize, rank))
+            .filter(|&(idx, _)| idx < self.entries.len())
+            .filter(|&(idx, _)| lang.is_none() || self.entries[idx].lang == lang)
+            .filter(|&(idx, _)| file.is_none_or(|f| self.entries[idx].file == f))
+            .filter(|&(idx, _)| files.as_ref().is_none_or(|files| files.contains(&self.entries[idx].file)))
+            .take(MAX_RESULTS);
+
+        for (idx, rank) in matching {
+            let mut entry = self.entries[idx].clone();
+            entry.rank = rank;
+            let mut buf = Vec::new();
+            let haystack = nucleo_matcher::Utf32Str::new(&entry.content, &mut buf);
+
+            let term_indices: Vec<Vec<u32>> = term_patterns
+                .iter()
+                .map(|p| {
+                    let mut found = Vec::new();
+                    p.indices(haystack, &mut matcher, &mut found);
+                    found
+                })
+                .collect();
+            let mut indices: Vec<u32> = term_indices.iter().flatten().copied().collect();
+            indices.sort_unstable();
+            indices.dedup();
+            entry.match_groups = indices
+                .iter()
+                .map(|i| term_indices.iter().position(|t| t.contains(i)).unwrap_or(0) as u8)
+                .collect();
+            entry.match_indices = indices;
+            results.push(entry);
         }
 
         results
     }
 
+    /// Names of indexed files whose path fuzzy-matches `pattern`.
+    fn files_matching(&self, pattern: &str) -> HashSet<String> {
+        let pattern = Pattern::parse(pattern, CaseMatching::Ignore, Normalization::Smart);
+        let mut matcher = Matcher::new(nucleo_matcher::Config::DEFAULT.match_paths());
+        let mut seen = HashSet::new();
+        let mut buf = Vec::new();
+
+        self.entries
+            .iter()
+            .map(|entry| entry.file.as_str())
+            .filter(|file| seen.insert(*file))
+            .filter(|file| pattern.score(nucleo_matcher::Utf32Str::new(file, &mut buf), &mut matcher).is_some())
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn search_regex(&self, re: &Regex, file: Option<&str>) -> Vec<SearchEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| file.is_none_or(|f| entry.file == f))
+            .filter_map(|entry| {
+                let mut indices = Vec::new();
+                for m in re.find_iter(&entry.content) {
+                    let start = entry.content[..m.start()].chars().count() as u32;
+                    let len = m.as_str().chars().count() as u32;
+                    indices.extend(start..start + len);
+                }
+                if indices.is_empty() && !re.is_match(&entry.content) {
+                    return None;
+                }
+                let mut entry = entry.clone();
+                entry.match_groups = vec![0; indices.len()];
+                entry.match_indices = indices;
+                Some(entry)
+            })
+            .take(MAX_RESULTS)
+            .collect()
+    }
+
     pub fn entry_count(&self) -> usize {
         self.entries.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_every_term_in_line_order() {
+        let files = vec![LoadedFile { name: "notes.md".into(), content: "alpha beta gamma".into() }];
+        let mut searcher = Searcher::from_files(&files, EntryMode::Line, false);
+        let results = searcher.search("gam alp", None);
+
+        let entry = &results[0];
+        assert_eq!(entry.match_indices, vec![0, 1, 2, 11, 12, 13]);
+        assert_eq!(entry.match_groups, vec![1, 1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn indented_lines_keep_their_indent_and_highlights() {
+        let files = vec![LoadedFile { name: "notes.md".into(), content: "- list\n    - nested item".into() }];
+        let mut searcher = Searcher::from_files(&files, EntryMode::Line, true);
+        let results = searcher.search_complete("nested");
+
+        let entry = &results[0];
+        assert_eq!(entry.content, "    - nested item");
+        assert_eq!(entry.match_indices, (6..12).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn scores_follow_the_ranking() {
+        let files = vec![LoadedFile { name: "notes.md".into(), content: "docker volumes\nd o c k e r".into() }];
+        let mut searcher = Searcher::from_files(&files, EntryMode::Line, false);
+        let results = searcher.search_complete("docker");
+
+        let scores: Vec<u32> = results.iter().map(|e| Searcher::score("docker", e)).collect();
+        assert_eq!(results.iter().map(|e| e.rank).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(scores[0] > scores[1] && scores[1] > 0);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::chat::ChatMessage;
+use crate::config::UserDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Session {
+    pub chat_messages: Vec<ChatMessage>,
+    pub quick_history: Vec<(String, String)>,
+}
+
+fn session_path(dirs: &UserDirs, cwd: &Path) -> Option<PathBuf> {
+    state_path(dirs, cwd, "sessions", "json")
+}
+
+fn last_dir_path(dirs: &UserDirs, start: &Path) -> Option<PathBuf> {
+    state_path(dirs, start, "last_dirs", "txt")
+}
+
+fn state_path(dirs: &UserDirs, cwd: &Path, kind: &str, ext: &str) -> Option<PathBuf> {
+    let hash = format!("{:x}", md5::compute(cwd.to_string_lossy().as_bytes()));
+    dirs.data.as_ref().map(|d| d.join(kind).join(format!("{}.{}", &hash[..16], ext)))
+}
+
+/// Directory last chosen in the picker when finder was started in `start`,
+/// if it still exists.
+pub fn load_last_dir(dirs: &UserDirs, start: &Path) -> Option<PathBuf> {
+    let content = fs::read_to_string(last_dir_path(dirs, start)?).ok()?;
+    let dir = PathBuf::from(content.trim()).canonicalize().ok()?;
+    dir.is_dir().then_some(dir)
+}
+
+pub fn save_last_dir(dirs: &UserDirs, start: &Path, dir: &Path) {
+    let Some(path) = last_dir_path(dirs, start) else { return };
+    let Some(parent) = path.parent() else { return };
+    if fs::create_dir_all(parent).is_ok() {
+        let _ = fs::write(path, dir.to_string_lossy().as_bytes());
+    }
+}
+
+pub fn load(dirs: &UserDirs, cwd: &Path) -> Option<Session> {
+    let content = fs::read_to_string(session_path(dirs, cwd)?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+pub fn save(dirs: &UserDirs, cwd: &Path, session: &Session) {
+    let Some(path) = session_path(dirs, cwd) else { return };
+    let Some(parent) = path.parent() else { return };
+    if fs::create_dir_all(parent).is_err() {
+        return;
+    }
+    let Ok(json) = serde_json::to_string(session) else { return };
+
+    // Write then rename so a crash mid-save never leaves a truncated file.
+    let tmp = path.with_extension("json.tmp");
+    if fs::write(&tmp, json).is_ok() {
+        let _ = fs::rename(&tmp, &path);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::theme::SyntaxColors;
+use ratatui::{
+    style::{Color, Style},
+    text::Span,
+};
+
+struct Language {
+    keywords: &'static [&'static str],
+    literals: &'static [&'static str],
+    line_comment: &'static str,
+}
+
+const RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn",
+        "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+        "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use", "where", "while",
+    ],
+    literals: &["true", "false", "None", "Some", "Ok", "Err"],
+    line_comment: "//",
+};
+
+const PYTHON: Language = Language {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else",
+        "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not",
+        "or", "pass", "raise", "return", "try", "while", "with", "yield",
+    ],
+    literals: &["True", "False", "None", "self"],
+    line_comment: "#",
+};
+
+const JAVASCRIPT: Language = Language {
+    keywords: &[
+        "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do",
+        "else", "export", "extends", "finally", "for", "from", "function", "if", "import", "in", "instanceof",
+        "interface", "let", "new", "of", "return", "switch", "this", "throw", "try", "type", "typeof", "var",
+        "void", "while", "yield",
+    ],
+    literals: &["true", "false", "null", "undefined"],
+    line_comment: "//",
+};
+
+const GO: Language = Language {
+    keywords: &[
+        "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "for", "func",
+        "go", "goto", "if", "import", "interface", "map", "package", "range", "return", "select", "struct",
+        "switch", "type", "var",
+    ],
+    literals: &["true", "false", "nil"],
+    line_comment: "//",
+};
+
+const SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local",
+        "return", "then", "until", "while",
+    ],
+    literals: &["true", "false"],
+    line_comment: "#",
+};
+
+const DATA: Language = Language {
+    keywords: &[],
+    literals: &["true", "false", "null"],
+    line_comment: "#",
+};
+
+fn language(lang: &str) -> Option<&'static Language> {
+    match lang.trim().to_lowercase().as_str() {
+        "rust" | "rs" => Some(&RUST),
+        "python" | "py" => Some(&PYTHON),
+        "javascript" | "js" | "typescript" | "ts" | "jsx" | "tsx" => Some(&JAVASCRIPT),
+        "go" | "golang" => Some(&GO),
+        "bash" | "sh" | "shell" | "zsh" => Some(&SHELL),
+        "json" | "toml" | "yaml" | "yml" => Some(&DATA),
+        _ => None,
+    }
+}
+
+/// Splits a code line into colored spans, or `None` for languages the
+/// tokenizer does not know. Tokens are recognised per line, so strings and
+/// comments spanning several lines are only colored on their first line.
+pub fn highlight_line(line: &str, lang: &str, base: Style, colors: &SyntaxColors) -> Option<Vec<Span<'static>>> {
+    let language = language(lang)?;
+    let chars: Vec<char> = line.chars().collect();
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut i = 0;
+
+    let push = |plain: &mut String, spans: &mut Vec<Span<'static>>, token: String, color: Option<Color>| {
+        if !plain.is_empty() {
+            spans.push(Span::styled(std::mem::take(plain), base));
+        }
+        spans.push(Span::styled(token, color.map_or(base, |c| base.fg(c))));
+    };
+
+    let comment: Vec<char> = language.line_comment.chars().collect();
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if chars[i..].starts_with(&comment) {
+            push(&mut plain, &mut spans, chars[i..].iter().collect(), Some(colors.comment));
+            break;
+        }
+
+        if c == '"' || c == '\'' || c == '`' {
+            let close = chars[i + 1..]
+                .iter()
+                .enumerate()
+                .scan(false, |escaped, (j, &ch)| {
+                    let closes = !*escaped && ch == c;
+                    *escaped = !*escaped && ch == '\\';
+                    Some((j, closes))
+                })
+                .find(|&(_, closes)| closes)
+                .map(|(j, _)| i + 1 + j + 1);
+            // An unmatched `'` is more likely a lifetime or apostrophe than a string.
+            let end = match close {
+                Some(end) => end,
+                None if c == '\'' => {
+                    plain.push(c);
+                    i += 1;
+                    continue;
+                }
+                None => chars.len(),
+            };
+            push(&mut plain, &mut spans, chars[i..end].iter().collect(), Some(colors.string));
+            i = end;
+            continue;
+        }
+
+        if c.is_ascii_digit() && (i == 0 || !is_ident(chars[i - 1])) {
+            let end = (i..chars.len())
+                .find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '.' || chars[j] == '_'))
+                .unwrap_or(chars.len());
+            push(&mut plain, &mut spans, chars[i..end].iter().collect(), Some(colors.number));
+            i = end;
+            continue;
+        }
+
+        if is_ident(c) {
+            let end = (i..chars.len()).find(|&j| !is_ident(chars[j])).unwrap_or(chars.len());
+            let word: String = chars[i..end].iter().collect();
+            let color = if language.keywords.contains(&word.as_str()) {
+                Some(colors.keyword)
+            } else if language.literals.contains(&word.as_str()) {
+                Some(colors.literal)
+            } else {
+                None
+            };
+            match color {
+                Some(_) => push(&mut plain, &mut spans, word, color),
+                None => plain.push_str(&word),
+            }
+            i = end;
+            continue;
+        }
+
+        plain.push(c);
+        i += 1;
+    }
+
+    if !plain.is_empty() {
+        spans.push(Span::styled(plain, base));
+    }
+    Some(spans)
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
@@ -0,0 +1,169 @@
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Colors used by the UI and the markdown renderer.
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub text: Color,
+    pub dim: Color,
+    pub accent: Color,
+    pub highlight: Color,
+    pub selection: Color,
+    pub code_fg: Color,
+    pub code_bg: Color,
+    pub error: Color,
+    pub warning: Color,
+    pub success: Color,
+    /// Colors for the second and later query terms; the first uses
+    /// `highlight`.
+    pub terms: [Color; 3],
+    pub syntax: SyntaxColors,
+}
+
+/// Token colors for fenced code blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct SyntaxColors {
+    pub keyword: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+    pub literal: Color,
+}
+
+impl Theme {
+    pub const DARK: Theme = Theme {
+        text: Color::White,
+        dim: Color::Rgb(128, 128, 128),
+        accent: Color::Rgb(100, 149, 237),
+        highlight: Color::Rgb(255, 200, 100),
+        selection: Color::Rgb(80, 80, 120),
+        code_fg: Color::Rgb(180, 180, 180),
+        code_bg: Color::Rgb(30, 30, 35),
+        error: Color::Red,
+        warning: Color::Yellow,
+        success: Color::Green,
+        terms: [Color::Rgb(120, 200, 255), Color::Rgb(150, 230, 150), Color::Rgb(230, 150, 230)],
+        syntax: SyntaxColors {
+            keyword: Color::Rgb(198, 120, 221),
+            string: Color::Rgb(152, 195, 121),
+            number: Color::Rgb(209, 154, 102),
+            comment: Color::Rgb(110, 110, 120),
+            literal: Color::Rgb(86, 182, 194),
+        },
+    };
+
+    pub const LIGHT: Theme = Theme {
+        text: Color::Black,
+        dim: Color::Rgb(90, 90, 90),
+        accent: Color::Rgb(30, 90, 200),
+        highlight: Color::Rgb(180, 90, 0),
+        selection: Color::Rgb(200, 200, 235),
+        code_fg: Color::Rgb(50, 50, 50),
+        code_bg: Color::Rgb(235, 235, 235),
+        error: Color::Rgb(180, 20, 20),
+        warning: Color::Rgb(150, 100, 0),
+        success: Color::Rgb(20, 120, 20),
+        terms: [Color::Rgb(0, 100, 180), Color::Rgb(30, 130, 30), Color::Rgb(150, 40, 150)],
+        syntax: SyntaxColors {
+            keyword: Color::Rgb(140, 40, 170),
+            string: Color::Rgb(50, 120, 30),
+            number: Color::Rgb(160, 80, 10),
+            comment: Color::Rgb(120, 120, 120),
+            literal: Color::Rgb(0, 120, 140),
+        },
+    };
+
+    /// Terminal defaults only, for monochrome terminals and custom palettes.
+    pub const MONO: Theme = Theme {
+        text: Color::Reset,
+        dim: Color::DarkGray,
+        accent: Color::Reset,
+        highlight: Color::Reset,
+        selection: Color::DarkGray,
+        code_fg: Color::Reset,
+        code_bg: Color::Reset,
+        error: Color::Reset,
+        warning: Color::Reset,
+        success: Color::Reset,
+        terms: [Color::Reset; 3],
+        syntax: SyntaxColors {
+            keyword: Color::Reset,
+            string: Color::Reset,
+            number: Color::Reset,
+            comment: Color::DarkGray,
+            literal: Color::Reset,
+        },
+    };
+
+    pub fn preset(name: &str) -> Option<Theme> {
+        match name.trim() {
+            "dark" => Some(Self::DARK),
+            "light" => Some(Self::LIGHT),
+            "mono" => Some(Self::MONO),
+            _ => None,
+        }
+    }
+
+    /// Applies a `[theme]` table on top of its preset; colors that are
+    /// missing or fail to parse keep the preset's value. An unknown preset
+    /// or color is described in the returned warnings.
+    pub fn from_config(config: &ThemeConfig) -> (Theme, Vec<String>) {
+        let mut warnings = Vec::new();
+        let mut theme = match config.preset.as_deref() {
+            Some(name) => Self::preset(name).unwrap_or_else(|| {
+                warnings.push(format!("unknown theme preset `{}`", name.trim()));
+                Self::DARK
+            }),
+            None => Self::DARK,
+        };
+
+        let fields = [
+            (&config.text, &mut theme.text),
+            (&config.dim, &mut theme.dim),
+            (&config.accent, &mut theme.accent),
+            (&config.highlight, &mut theme.highlight),
+            (&config.selection, &mut theme.selection),
+            (&config.code_fg, &mut theme.code_fg),
+            (&config.code_bg, &mut theme.code_bg),
+            (&config.error, &mut theme.error),
+            (&config.warning, &mut theme.warning),
+            (&config.success, &mut theme.success),
+            (&config.keyword, &mut theme.syntax.keyword),
+            (&config.string, &mut theme.syntax.string),
+            (&config.number, &mut theme.syntax.number),
+            (&config.comment, &mut theme.syntax.comment),
+            (&config.literal, &mut theme.syntax.literal),
+        ];
+        for (value, slot) in fields {
+            let Some(value) = value.as_deref() else { continue };
+            match Color::from_str(value.trim()) {
+                Ok(color) => *slot = color,
+                Err(_) => warnings.push(format!("invalid theme color `{}`", value.trim())),
+            }
+        }
+        (theme, warnings)
+    }
+}
+
+/// The `[theme]` table of `.finder.toml`. Colors are names (`blue`,
+/// `darkgray`) or `#rrggbb`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ThemeConfig {
+    pub preset: Option<String>,
+    pub text: Option<String>,
+    pub dim: Option<String>,
+    pub accent: Option<String>,
+    pub highlight: Option<String>,
+    pub selection: Option<String>,
+    pub code_fg: Option<String>,
+    pub code_bg: Option<String>,
+    pub error: Option<String>,
+    pub warning: Option<String>,
+    pub success: Option<String>,
+    pub keyword: Option<String>,
+    pub string: Option<String>,
+    pub number: Option<String>,
+    pub comment: Option<String>,
+    pub literal: Option<String>,
+}
@@ -1,32 +1,168 @@
-use crate::app::{App, Mode};
+use crate::app::{preview_window_start, App, ChatLink, ChatRender, ContextMenu, Mode, ResultAction};
 use crate::compass::COMPASS;
+use crate::keymap::Action;
+use crate::markdown::LinkSpan;
+use crate::preview::Preview;
+use crate::search::{EntryMode, SearchEntry};
+use crate::theme::Theme;
+use crate::util;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use ratatui::{
-    layout::{Constraint, Layout, Rect},
+    layout::{Constraint, Layout, Margin, Position, Rect},
     style::{Color, Modifier, Style},
-    text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Padding, Paragraph, Wrap},
+    text::{Line, Span, Text},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, Padding, Paragraph, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Wrap,
+    },
     Frame,
 };
+use std::ops::Range;
+use std::rc::Rc;
 
-const BLUE: Color = Color::Rgb(100, 149, 237);
-const DIM: Color = Color::Rgb(128, 128, 128);
-const HIGHLIGHT: Color = Color::Rgb(255, 200, 100);
+/// Rows of the expanded quick-answer sources panel.
+const QUICK_SOURCES_VISIBLE: usize = 5;
 
 pub fn draw(frame: &mut Frame, app: &App, sel_start: Option<(u16, u16)>, sel_end: Option<(u16, u16)>) {
-    match app.mode {
+    let mode = match app.mode {
+        Mode::Help => app.previous_mode,
+        Mode::Conversations => Mode::Chat,
+        mode => mode,
+    };
+    match mode {
         Mode::Search => draw_search(frame, app),
         Mode::Chat => draw_chat(frame, app),
         Mode::Citations => draw_citations(frame, app),
         Mode::DirectoryPicker => draw_directory_picker(frame, app),
         Mode::QuickAnswer => draw_quick_answer(frame, app),
+        Mode::Compare => draw_compare(frame, app),
+        Mode::Conversations | Mode::Help => {}
+    }
+
+    match app.mode {
+        Mode::Conversations => draw_conversations(frame, app),
+        Mode::Help => draw_help(frame, app),
+        _ => {}
+    }
+
+    if let Some(menu) = &app.context_menu {
+        draw_context_menu(frame, menu, &app.theme);
     }
 
     if let (Some(start), Some(end)) = (sel_start, sel_end) {
-        draw_selection(frame, start, end);
+        draw_selection(frame, start, end, &app.theme);
     }
 }
 
-fn draw_selection(frame: &mut Frame, start: (u16, u16), end: (u16, u16)) {
+/// Index of the citation, directory or quick-answer source under the
+/// cursor, in the list the current mode shows.
+pub fn list_item_at(area: Rect, app: &App, col: u16, row: u16) -> Option<usize> {
+    let (list, rows_per_item, selected, count) = match app.mode {
+        Mode::Citations => (
+            picker_list_block().inner(citations_content_layout(citations_layout(area)[2])[0]),
+            2,
+            app.citations_selected,
+            app.citations_count(),
+        ),
+        Mode::DirectoryPicker => (
+            picker_list_block().inner(citations_content_layout(citations_layout(area)[2])[0]),
+            1,
+            app.dir_selected,
+            app.dir_list().len(),
+        ),
+        Mode::QuickAnswer if app.quick_sources_expanded => {
+            let sources = quick_response_layout(quick_layout(area, app)[2], app).1?;
+            let inner = quick_sources_block().inner(sources);
+            (inner, 1, app.quick_sources_selected, app.quick_sources.len())
+        }
+        _ => return None,
+    };
+    if !list.contains(Position::new(col, row)) {
+        return None;
+    }
+
+    let visible = list.height as usize / rows_per_item;
+    let idx = list_skip(selected, visible) + (row - list.y) as usize / rows_per_item;
+    (idx < count).then_some(idx)
+}
+
+pub fn result_at(area: Rect, app: &App, col: u16, row: u16) -> Option<usize> {
+    let inner = results_list_block().inner(results_list_area(area, app));
+    if !inner.contains(Position::new(col, row)) {
+        return None;
+    }
+
+    let mut top = inner.y as usize;
+    for idx in results_skip(app, inner)..app.results.len() {
+        let bottom = top + result_rows(app, idx, inner);
+        if (row as usize) < bottom {
+            return Some(idx);
+        }
+        top = bottom;
+    }
+    None
+}
+
+pub fn context_menu_area(area: Rect, menu: &ContextMenu) -> Rect {
+    let width = 20.min(area.width);
+    let height = (ResultAction::ALL.len() as u16 + 2).min(area.height);
+    let x = menu.position.0.min(area.width.saturating_sub(width));
+    let y = menu.position.1.min(area.height.saturating_sub(height));
+    Rect::new(x, y, width, height)
+}
+
+pub fn context_menu_item_at(area: Rect, menu: &ContextMenu, col: u16, row: u16) -> Option<usize> {
+    let inner = context_menu_block().inner(context_menu_area(area, menu));
+    if !inner.contains(Position::new(col, row)) {
+        return None;
+    }
+    let idx = (row - inner.y) as usize;
+    (idx < ResultAction::ALL.len()).then_some(idx)
+}
+
+fn context_menu_block() -> Block<'static> {
+    Block::default()
+        .borders(Borders::ALL)
+        .padding(Padding::horizontal(1))
+}
+
+fn draw_context_menu(frame: &mut Frame, menu: &ContextMenu, theme: &Theme) {
+    let area = context_menu_area(frame.area(), menu);
+    let block = context_menu_block().border_style(Style::default().fg(theme.accent));
+    let inner = block.inner(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
+
+    let items: Vec<ListItem> = ResultAction::ALL
+        .iter()
+        .enumerate()
+        .map(|(idx, action)| {
+            let is_selected = idx == menu.selected;
+            let marker = if is_selected { ">" } else { " " };
+            let style = if is_selected {
+                Style::default().fg(theme.text).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(theme.dim)
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(marker, Style::default().fg(theme.accent)),
+                Span::styled(format!(" {}", action.label()), style),
+            ]))
+        })
+        .collect();
+
+    frame.render_widget(List::new(items), inner);
+}
+
+fn list_skip(selected: usize, visible_height: usize) -> usize {
+    if selected >= visible_height {
+        selected - visible_height + 1
+    } else {
+        0
+    }
+}
+
+fn draw_selection(frame: &mut Frame, start: (u16, u16), end: (u16, u16), theme: &Theme) {
     let (start, end) = if start.1 < end.1 || (start.1 == end.1 && start.0 <= end.0) {
         (start, end)
     } else {
@@ -34,7 +170,7 @@ fn draw_selection(frame: &mut Frame, start: (u16, u16), end: (u16, u16)) {
     };
 
     let area = frame.area();
-    let sel_style = Style::default().bg(Color::Rgb(80, 80, 120));
+    let sel_style = Style::default().bg(theme.selection);
 
     for row in start.1..=end.1 {
         if row >= area.height {
@@ -61,33 +197,299 @@ fn calc_input_height(text_len: usize, width: u16) -> u16 {
     (lines as u16 + 2).max(3)
 }
 
-fn draw_search(frame: &mut Frame, app: &App) {
-    let area = frame.area();
+fn search_layout(area: Rect, app: &App) -> Rc<[Rect]> {
     let input_height = calc_input_height(app.query.len(), area.width);
 
-    let chunks = Layout::vertical([
+    Layout::vertical([
         Constraint::Length(5),
         Constraint::Length(input_height),
         Constraint::Min(1),
     ])
-    .split(area);
+    .split(area)
+}
+
+fn results_layout(area: Rect) -> Rc<[Rect]> {
+    Layout::horizontal([
+        Constraint::Percentage(50),
+        Constraint::Percentage(50),
+    ])
+    .split(area)
+}
+
+fn results_list_area(area: Rect, app: &App) -> Rect {
+    let results = search_layout(area, app)[2];
+    if app.inline_context {
+        results
+    } else {
+        results_layout(results)[0]
+    }
+}
+
+fn results_list_block() -> Block<'static> {
+    Block::default()
+        .borders(Borders::ALL)
+        .padding(Padding::new(2, 2, 1, 1))
+}
+
+fn draw_search(frame: &mut Frame, app: &App) {
+    let chunks = search_layout(frame.area(), app);
 
     draw_header(frame, chunks[0], app);
     draw_search_input(frame, chunks[1], app);
     draw_results(frame, chunks[2], app);
 }
 
-fn draw_chat(frame: &mut Frame, app: &App) {
-    let area = frame.area();
-    let input_height = calc_input_height(app.chat_input.len(), area.width);
+/// Key bindings per mode. Rebindable actions show their current key.
+fn help_sections(app: &App) -> Vec<(&'static str, Vec<(String, &'static str)>)> {
+    let key = |action| app.keymap.label(action);
+    let fixed = |label: &str| label.to_string();
 
+    vec![
+        ("Everywhere", vec![
+            (key(Action::Help), "show or hide this help"),
+            (key(Action::Wrap), "toggle line wrapping"),
+            (fixed("Esc"), "back / quit"),
+        ]),
+        ("Search", vec![
+            (fixed("Enter"), "open the selected result in the editor"),
+            (key(Action::OpenInPlace), "open it and come back here"),
+            (fixed("Up/Down"), "move the selection"),
+            (fixed("Tab"), "expand the selected result"),
+            (fixed("Left/Right"), "scroll the preview sideways"),
+            (key(Action::Chat), "chat about the files"),
+            (key(Action::QuickAnswer), "quick answer (empty query)"),
+            (key(Action::DirectoryPicker), "change directory"),
+            (key(Action::ExtensionFilter), "filter by extension"),
+            (key(Action::Regex), "toggle regex search"),
+            (key(Action::FileScope), "limit to the selected file"),
+            (key(Action::Compare), "mark a file for comparison"),
+            (format!("{}/{}", key(Action::HistoryPrev), key(Action::HistoryNext)), "search history"),
+            (key(Action::CopyMatch), "copy the matched line"),
+            (key(Action::CopySnippet), "copy the preview snippet"),
+            (key(Action::CopyLocation), "copy file:line"),
+            (key(Action::InlineContext), "context lines / preview pane"),
+            (key(Action::Refresh), "re-index the directory"),
+        ]),
+        ("Chat", vec![
+            (fixed("Enter"), "send the message"),
+            (fixed("Up/Down"), "scroll the conversation"),
+            (fixed("[ / ]"), "previous / next heading"),
+            (key(Action::Citations), "browse citations"),
+            (key(Action::ChatRetrieval), "send relevant sections / every file"),
+            (key(Action::SaveChat), "save the conversation"),
+            (key(Action::Conversations), "reopen a saved conversation"),
+            (key(Action::ReloadKey), "reload the API key"),
+            (key(Action::DirectoryPicker), "change directory"),
+            (fixed("Ctrl+L"), "recall the last question"),
+            (fixed("Ctrl+P"), "show the system prompt"),
+            (fixed("Ctrl+C"), "cancel the response"),
+        ]),
+        ("Citations", vec![
+            (fixed("Enter"), "open the citation in the editor"),
+            (fixed("Up/Down"), "move the selection"),
+            (fixed("PgUp/PgDn"), "scroll the preview"),
+        ]),
+        ("Directory picker", vec![
+            (fixed("Enter"), "switch to the selected directory"),
+            (fixed("Up/Down"), "move the selection"),
+            (fixed("Type"), "filter the directories"),
+            (fixed("Esc"), "back to search"),
+        ]),
+        ("Quick answer", vec![
+            (fixed("Enter"), "ask / open the selected source"),
+            (fixed("Tab"), "show or hide sources"),
+            (fixed("Ctrl+F"), "continue in chat"),
+            (fixed("Ctrl+N"), "new conversation"),
+            (fixed("Ctrl+R"), "rebuild the index"),
+            (fixed("Ctrl+P"), "show the system prompt"),
+            (key(Action::ReloadKey), "reload the API key"),
+            (fixed("Ctrl+C"), "cancel the response"),
+        ]),
+        ("Compare", vec![
+            (fixed("Up/Down"), "scroll both files"),
+            (fixed("PgUp/PgDn"), "scroll a page"),
+        ]),
+    ]
+}
+
+fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn draw_conversations(frame: &mut Frame, app: &App) {
+    let theme = &app.theme;
+    let area = centered_rect(frame.area(), 72, app.saved_conversations.len() as u16 + 2);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .padding(Padding::horizontal(1))
+        .title(Span::styled(" Saved conversations (Enter to open) ", Style::default().fg(theme.accent)));
+    let inner = block.inner(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
+
+    let visible = inner.height as usize;
+    let items: Vec<ListItem> = app
+        .saved_conversations
+        .iter()
+        .enumerate()
+        .skip(list_skip(app.conversations_selected, visible))
+        .map(|(idx, conversation)| {
+            let is_selected = idx == app.conversations_selected;
+            let marker = if is_selected { ">" } else { " " };
+            let style = if is_selected {
+                Style::default().fg(theme.text).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(theme.dim)
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(marker, Style::default().fg(theme.accent)),
+                Span::styled(format!(" {:>14}  ", conversation.age()), Style::default().fg(theme.dim)),
+                Span::styled(conversation.title().to_string(), style),
+            ]))
+        })
+        .collect();
+    frame.render_widget(List::new(items), inner);
+}
+
+fn draw_help(frame: &mut Frame, app: &App) {
+    let theme = &app.theme;
+    let sections = help_sections(app);
+    let key_width = sections
+        .iter()
+        .flat_map(|(_, keys)| keys.iter().map(|(k, _)| k.chars().count()))
+        .max()
+        .unwrap_or(0);
+
+    let mut lines = Vec::new();
+    for (title, keys) in sections {
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from(Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD))));
+        for (key, description) in keys {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {:<width$}  ", key, width = key_width), Style::default().fg(theme.highlight)),
+                Span::styled(description, Style::default().fg(theme.text)),
+            ]));
+        }
+    }
+
+    let area = centered_rect(frame.area(), 64, lines.len() as u16 + 2);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .padding(Padding::horizontal(1))
+        .title(Span::styled(" Key bindings (Esc to close) ", Style::default().fg(theme.accent)));
+    let inner = block.inner(area);
+    let total = lines.len();
+    let visible = inner.height as usize;
+    app.help_max_scroll.set(total.saturating_sub(visible));
+    let scroll = app.help_scroll.min(total.saturating_sub(visible));
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
+    frame.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), inner);
+    draw_scrollbar(frame, area, total, scroll, visible, theme);
+}
+
+fn draw_compare(frame: &mut Frame, app: &App) {
+    let theme = &app.theme;
     let chunks = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Min(1),
+        Constraint::Length(3),
+    ])
+    .split(frame.area());
+
+    draw_header(frame, chunks[0], app);
+
+    if let Some((left, right)) = &app.compare_files {
+        let panes = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(chunks[1]);
+        let loaded = |file: &str| match app.previews.get(&app.resolve(file)) {
+            Preview::Ready(content) => content,
+            _ => "",
+        };
+        let left_content = loaded(left);
+        let right_content = loaded(right);
+        let left_lines: Vec<&str> = left_content.lines().collect();
+        let right_lines: Vec<&str> = right_content.lines().collect();
+
+        draw_compare_pane(frame, panes[0], left, &left_lines, &right_lines, app);
+        draw_compare_pane(frame, panes[1], right, &right_lines, &left_lines, app);
+    }
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.dim))
+        .padding(Padding::horizontal(1));
+    let inner = block.inner(chunks[2]);
+    frame.render_widget(block, chunks[2]);
+
+    let hints = vec![
+        Span::styled("differing lines are bright  ", Style::default().fg(theme.dim)),
+        Span::styled("[Up/Down]", Style::default().fg(theme.accent)),
+        Span::styled(" scroll  ", Style::default().fg(theme.dim)),
+        Span::styled("[PgUp/PgDn]", Style::default().fg(theme.accent)),
+        Span::styled(" page  ", Style::default().fg(theme.dim)),
+        Span::styled("[Esc]", Style::default().fg(theme.accent)),
+        Span::styled(" back", Style::default().fg(theme.dim)),
+    ];
+    frame.render_widget(Paragraph::new(Line::from(hints)), inner);
+}
+
+fn draw_compare_pane(
+    frame: &mut Frame,
+    area: Rect,
+    name: &str,
+    lines: &[&str],
+    other: &[&str],
+    app: &App,
+) {
+    let theme = &app.theme;
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.dim))
+        .title(Span::styled(format!(" {} ", name), Style::default().fg(theme.text)))
+        .padding(Padding::new(1, 1, 0, 0));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let differs = |line_num: usize| other.get(line_num - 1) != lines.get(line_num - 1);
+    let rows = preview_rows(lines, app.compare_scroll, differs, 0, inner, app);
+    frame.render_widget(Paragraph::new(rows), inner);
+}
+
+fn chat_layout(area: Rect, app: &App) -> Rc<[Rect]> {
+    let input_height = calc_input_height(app.chat_input.len(), area.width);
+    Layout::vertical([
         Constraint::Length(5),
         Constraint::Length(input_height),
         Constraint::Min(1),
         Constraint::Length(3),
     ])
-    .split(area);
+    .split(area)
+}
+
+fn chat_response_block(theme: &Theme) -> Block<'static> {
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.dim))
+        .padding(Padding::new(2, 2, 1, 1))
+}
+
+fn draw_chat(frame: &mut Frame, app: &App) {
+    let chunks = chat_layout(frame.area(), app);
 
     draw_header(frame, chunks[0], app);
     draw_chat_input(frame, chunks[1], app);
@@ -96,22 +498,25 @@ fn draw_chat(frame: &mut Frame, app: &App) {
 }
 
 fn draw_header(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM));
+        .border_style(Style::default().fg(theme.dim));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
-    let compass_style = Style::default().fg(BLUE);
-    let text_style = Style::default().fg(Color::White);
-    let dim_style = Style::default().fg(DIM);
+    let compass_style = Style::default().fg(theme.accent);
+    let text_style = Style::default().fg(theme.text);
+    let dim_style = Style::default().fg(theme.dim);
 
     let cwd_display = app
         .cwd
         .to_string_lossy()
         .replace(
-            dirs::home_dir()
+            app.dirs
+                .home
+                .as_ref()
                 .map(|h| h.to_string_lossy().to_string())
                 .unwrap_or_default()
                 .as_str(),
@@ -120,10 +525,19 @@ fn draw_header(frame: &mut Frame, area: Rect, app: &App) {
 
     let mode_indicator = match app.mode {
         Mode::Search => "",
+        Mode::Chat if app.chat_retrieval => " [CHAT · RETRIEVAL]",
         Mode::Chat => " [CHAT]",
         Mode::Citations => " [CITATIONS]",
         Mode::DirectoryPicker => " [DIRECTORY]",
         Mode::QuickAnswer => " [QUICK]",
+        Mode::Compare => " [COMPARE]",
+        Mode::Conversations => " [SAVED CHATS]",
+        Mode::Help => " [HELP]",
+    };
+
+    let entry_unit = match app.config.entry_mode {
+        EntryMode::Line => "lines",
+        EntryMode::Section => "sections",
     };
 
     let lines: Vec<Line> = vec![
@@ -131,15 +545,29 @@ fn draw_header(frame: &mut Frame, area: Rect, app: &App) {
             Span::styled(COMPASS[0], compass_style),
             Span::styled("  Finder ", text_style.add_modifier(Modifier::BOLD)),
             Span::styled("v0.1.0", dim_style),
-            Span::styled(mode_indicator, Style::default().fg(BLUE)),
+            Span::styled(mode_indicator, Style::default().fg(theme.accent)),
+            Span::styled(format!("  {} · {}", app.provider.label(), app.model), dim_style),
         ]),
         Line::from(vec![
             Span::styled(COMPASS[1], compass_style),
             Span::styled(format!("  {}", cwd_display), dim_style),
+            Span::styled(
+                app.file_scope
+                    .as_ref()
+                    .map(|f| format!("  [only {}]", f))
+                    .unwrap_or_default(),
+                Style::default().fg(theme.accent),
+            ),
         ]),
         Line::from(vec![
             Span::styled(COMPASS[2], compass_style),
-            Span::styled(format!("  {} lines indexed", app.entry_count), dim_style),
+            Span::styled(format!("  {} {} indexed", app.entry_count, entry_unit), dim_style),
+            Span::styled(
+                app.rag_indexing()
+                    .map(|n| format!("  · indexing {} files for quick answers…", n))
+                    .unwrap_or_default(),
+                Style::default().fg(theme.warning),
+            ),
         ]),
     ];
 
@@ -148,49 +576,62 @@ fn draw_header(frame: &mut Frame, area: Rect, app: &App) {
 }
 
 fn draw_search_input(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
-    let text = format!("> {}_", app.query);
-    let paragraph = Paragraph::new(text)
-        .style(Style::default().fg(Color::White))
+    let prompt = if app.regex_mode { "/" } else { ">" };
+    let mut spans = vec![Span::raw(format!("{} {}_", prompt, app.query))];
+    if app.regex_error {
+        spans.push(Span::styled("  invalid pattern", Style::default().fg(theme.error)));
+    }
+    if let Some(ext) = &app.extension_filter {
+        spans.push(Span::styled(format!("  [.{} only]", ext), Style::default().fg(theme.accent)));
+    }
+    if let Some((msg, instant)) = &app.status_message {
+        if instant.elapsed().as_secs() < 3 {
+            spans.push(Span::styled(format!("  ⚠ {}", msg), Style::default().fg(theme.warning)));
+        }
+    }
+    let paragraph = Paragraph::new(Line::from(spans))
+        .style(Style::default().fg(theme.text))
         .wrap(Wrap { trim: false })
         .block(block);
     frame.render_widget(paragraph, area);
 }
 
 fn draw_chat_input(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let text = format!("? {}_", app.chat_input);
     let paragraph = Paragraph::new(text)
-        .style(Style::default().fg(Color::White))
+        .style(Style::default().fg(theme.text))
         .wrap(Wrap { trim: false })
         .block(block);
     frame.render_widget(paragraph, area);
 }
 
 fn draw_results(frame: &mut Frame, area: Rect, app: &App) {
-    let chunks = Layout::horizontal([
-        Constraint::Percentage(50),
-        Constraint::Percentage(50),
-    ])
-    .split(area);
+    if app.inline_context {
+        draw_results_list(frame, area, app);
+        return;
+    }
+
+    let chunks = results_layout(area);
 
     draw_results_list(frame, chunks[0], app);
     draw_preview(frame, chunks[1], app);
 }
 
 fn draw_results_list(frame: &mut Frame, area: Rect, app: &App) {
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
-        .padding(Padding::new(2, 2, 1, 1));
+    let theme = &app.theme;
+    let block = results_list_block().border_style(Style::default().fg(theme.dim));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
@@ -201,62 +642,80 @@ fn draw_results_list(frame: &mut Frame, area: Rect, app: &App) {
         } else {
             "No results"
         };
-        let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(DIM)));
+        let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(theme.dim)));
         frame.render_widget(paragraph, inner);
         return;
     }
 
-    let visible_height = inner.height as usize / 3;
-    let skip = if app.selected >= visible_height {
-        app.selected - visible_height + 1
-    } else {
-        0
-    };
+    let skip = results_skip(app, inner);
+    draw_scrollbar(frame, area, app.results.len(), skip, inner.height as usize, theme);
     let items: Vec<ListItem> = app
         .results
         .iter()
         .enumerate()
         .skip(skip)
-        .take(visible_height)
+        .take(inner.height as usize)
         .map(|(idx, entry)| {
             let is_selected = idx == app.selected;
             let marker = if is_selected { ">" } else { " " };
-            let marker_style = Style::default().fg(BLUE);
+            let marker_style = Style::default().fg(theme.accent);
 
             let file_style = if is_selected {
                 Style::default()
-                    .fg(Color::White)
+                    .fg(theme.text)
                     .add_modifier(Modifier::BOLD)
             } else {
-                Style::default().fg(Color::White)
+                Style::default().fg(theme.text)
             };
 
-            let content_style = Style::default().fg(DIM);
+            let content_style = Style::default().fg(theme.dim);
+            let mut header_spans = vec![
+                Span::styled(marker, marker_style),
+                Span::styled(format!(" {}:{}", entry.file, entry.line_num), file_style),
+            ];
+            if app.is_stale(&entry.file) {
+                header_spans.push(Span::styled("  (deleted)", Style::default().fg(theme.error)));
+            }
+            let header = Line::from(header_spans);
 
             let max_content_width = area.width.saturating_sub(8) as usize;
-            let truncated_content: String = entry.content.chars().take(max_content_width).collect();
+            let (before, after) = if app.inline_context {
+                let (before, after) = app.line_context(&entry.file, entry.line_num);
+                let rows = |lines: Vec<Option<&str>>| -> Vec<Line> {
+                    lines.into_iter().map(|l| context_line(l, max_content_width, theme)).collect()
+                };
+                (rows(before), rows(after))
+            } else {
+                (Vec::new(), Vec::new())
+            };
+
+            if is_expanded(app, idx) {
+                let mut lines = vec![header];
+                lines.extend(before);
+                lines.extend(expanded_content_lines(app, entry, expanded_width(inner), content_style));
+                lines.extend(after);
+                lines.push(Line::from(""));
+                return ListItem::new(lines);
+            }
+
+            let truncated_content = fit_width(&entry.content, max_content_width);
             let truncated_len = truncated_content.chars().count();
-            let suffix = if entry.content.chars().count() > max_content_width { "..." } else { "" };
+            let suffix = if truncated_content.len() < entry.content.len() { "..." } else { "" };
 
-            let truncated_indices: Vec<u32> = entry
-                .match_indices
-                .iter()
-                .filter(|&&i| (i as usize) < truncated_len)
-                .copied()
+            let truncated_marks: Vec<(u32, u8)> = match_marks(app, entry)
+                .into_iter()
+                .filter(|&(i, _)| (i as usize) < truncated_len)
                 .collect();
 
             let mut content_spans = vec![Span::raw("  \"")];
-            content_spans.extend(highlight_text(&truncated_content, &truncated_indices, content_style));
+            content_spans.extend(highlight_text(truncated_content, &truncated_marks, content_style, &app.theme));
             content_spans.push(Span::styled(format!("{}\"", suffix), content_style));
 
-            let lines = vec![
-                Line::from(vec![
-                    Span::styled(marker, marker_style),
-                    Span::styled(format!(" {}:{}", entry.file, entry.line_num), file_style),
-                ]),
-                Line::from(content_spans),
-                Line::from(""),
-            ];
+            let mut lines = vec![header];
+            lines.extend(before);
+            lines.push(Line::from(content_spans));
+            lines.extend(after);
+            lines.push(Line::from(""));
 
             ListItem::new(lines)
         })
@@ -266,26 +725,218 @@ fn draw_results_list(frame: &mut Frame, area: Rect, app: &App) {
     frame.render_widget(list, inner);
 }
 
-fn draw_preview(frame: &mut Frame, area: Rect, app: &App) {
-    let block = Block::default()
+fn expanded_width(inner: Rect) -> usize {
+    (inner.width.saturating_sub(4) as usize).max(1)
+}
+
+/// Splits `chars` into rows of at most `width` terminal columns, breaking
+/// after whitespace where possible and mid-word only when a word is longer
+/// than a row. Wide characters count as two columns, like in `fit_width`.
+fn wrap_words(chars: &[char], width: usize) -> Vec<Range<usize>> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut start = 0;
+
+    loop {
+        let mut used = 0;
+        let mut limit = start;
+        while limit < chars.len() {
+            let w = chars[limit].width().unwrap_or(0);
+            if used + w > width {
+                break;
+            }
+            used += w;
+            limit += 1;
+        }
+        if limit == chars.len() {
+            break;
+        }
+        // A character wider than the whole row still gets a row of its own.
+        let limit = limit.max(start + 1);
+        let end = (start + 1..=limit)
+            .rev()
+            .find(|&i| chars[i - 1].is_whitespace())
+            .unwrap_or(limit);
+        rows.push(start..end);
+        start = end;
+    }
+    rows.push(start..chars.len());
+    rows
+}
+
+fn wrapped_rows(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    wrap_words(&chars, width)
+        .into_iter()
+        .map(|r| chars[r].iter().collect())
+        .collect()
+}
+
+/// Preview rows for lines `start..` of a file, each wrapped under its line
+/// number, until `height` rows are filled.
+fn wrapped_preview_lines(
+    lines: &[&str],
+    start: usize,
+    target: impl Fn(usize) -> bool,
+    width: usize,
+    height: usize,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let mut rows = Vec::new();
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        let line_num = i + 1;
+        let is_target = target(line_num);
+        let line_num_style = Style::default().fg(if is_target { theme.highlight } else { theme.dim });
+        let content_style = Style::default().fg(if is_target { theme.text } else { theme.dim });
+
+        for (r, row) in wrapped_rows(line, width).into_iter().enumerate() {
+            if rows.len() == height {
+                return rows;
+            }
+            let gutter = if r == 0 { format!("{:>4} ", line_num) } else { "     ".to_string() };
+            rows.push(Line::from(vec![
+                Span::styled(gutter, line_num_style),
+                Span::styled(row, content_style),
+            ]));
+        }
+    }
+    rows
+}
+
+/// First line to show so the wrapped target line sits near the middle.
+fn wrapped_window_start(lines: &[&str], target_idx: usize, width: usize, height: usize) -> usize {
+    let mut start = target_idx.min(lines.len().saturating_sub(1));
+    let mut rows_above = 0;
+    while start > 0 {
+        let rows = wrapped_rows(lines[start - 1], width).len();
+        if rows_above + rows > height / 2 {
+            break;
+        }
+        rows_above += rows;
+        start -= 1;
+    }
+    start
+}
+
+fn expanded_content_lines(app: &App, entry: &SearchEntry, width: usize, style: Style) -> Vec<Line<'static>> {
+    let marks = match_marks(app, entry);
+    let chars: Vec<char> = entry.content.chars().collect();
+    let rows = wrap_words(&chars, width);
+    let last = rows.len() - 1;
+
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let offset = row.start as u32;
+            let text: String = chars[row.clone()].iter().collect();
+            let chunk_marks: Vec<(u32, u8)> = marks
+                .iter()
+                .filter(|&&(m, _)| m >= offset && m < row.end as u32)
+                .map(|&(m, group)| (m - offset, group))
+                .collect();
+
+            let mut spans = vec![Span::raw(if i == 0 { "  \"" } else { "   " })];
+            spans.extend(highlight_text(&text, &chunk_marks, style, &app.theme));
+            if i == last {
+                spans.push(Span::styled("\"", style));
+            }
+            Line::from(spans)
+        })
+        .collect()
+}
+
+fn is_expanded(app: &App, idx: usize) -> bool {
+    app.wrap_lines || (app.result_expanded && idx == app.selected)
+}
+
+fn result_rows(app: &App, idx: usize, inner: Rect) -> usize {
+    let content_rows = if is_expanded(app, idx) {
+        app.results.get(idx).map_or(1, |e| {
+            let chars: Vec<char> = e.content.chars().collect();
+            wrap_words(&chars, expanded_width(inner)).len()
+        })
+    } else {
+        1
+    };
+    let context_rows = if app.inline_context { 2 * app.config.context_lines } else { 0 };
+    2 + content_rows + context_rows
+}
+
+/// Longest prefix of `text` that fits in `width` terminal columns, so wide
+/// CJK characters and emoji count as the two columns they take.
+fn fit_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in text.char_indices() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+fn context_line(text: Option<&str>, width: usize, theme: &Theme) -> Line<'static> {
+    let truncated = fit_width(text.unwrap_or("").trim(), width);
+    Line::from(Span::styled(
+        format!("   {}", truncated),
+        Style::default().fg(theme.dim),
+    ))
+}
+
+fn results_skip(app: &App, inner: Rect) -> usize {
+    let height = inner.height as usize;
+    let mut skip = list_skip(app.selected, height / 3);
+    while skip < app.selected
+        && (skip..=app.selected).map(|i| result_rows(app, i, inner)).sum::<usize>() > height
+    {
+        skip += 1;
+    }
+    skip
+}
+
+fn preview_message(preview: &Preview, stale: bool) -> &'static str {
+    match preview {
+        Preview::Loading => "loading…",
+        _ if stale => "File was deleted after indexing. Press F5 to refresh the index.",
+        _ => "Cannot read file",
+    }
+}
+
+fn preview_block() -> Block<'static> {
+    Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
-        .padding(Padding::new(2, 2, 1, 1));
+        .padding(Padding::new(2, 2, 1, 1))
+}
+
+pub fn in_search_preview(area: Rect, app: &App, col: u16, row: u16) -> bool {
+    !app.inline_context && results_layout(search_layout(area, app)[2])[1].contains(Position::new(col, row))
+}
+
+pub fn search_preview_height(area: Rect, app: &App) -> usize {
+    preview_block().inner(results_layout(search_layout(area, app)[2])[1]).height as usize
+}
+
+fn draw_preview(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
+    let block = preview_block().border_style(Style::default().fg(theme.dim));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
     let Some(entry) = app.results.get(app.selected) else {
-        let paragraph = Paragraph::new(Span::styled("No preview", Style::default().fg(DIM)));
+        let paragraph = Paragraph::new(Span::styled("No preview", Style::default().fg(theme.dim)));
         frame.render_widget(paragraph, inner);
         return;
     };
 
-    let file_path = app.cwd.join(&entry.file);
-    let Ok(content) = std::fs::read_to_string(&file_path) else {
-        let paragraph = Paragraph::new(Span::styled("Cannot read file", Style::default().fg(DIM)));
-        frame.render_widget(paragraph, inner);
-        return;
+    let content = match app.previews.get(&app.resolve(&entry.file)) {
+        Preview::Ready(content) => content,
+        preview => {
+            let msg = preview_message(&preview, app.is_stale(&entry.file));
+            let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(theme.dim)));
+            frame.render_widget(paragraph, inner);
+            return;
+        }
     };
 
     let lines: Vec<&str> = content.lines().collect();
@@ -293,61 +944,193 @@ fn draw_preview(frame: &mut Frame, area: Rect, app: &App) {
     let visible_lines = inner.height as usize;
     let half_visible = visible_lines / 2;
 
-    let start_line = target_line.saturating_sub(half_visible);
-    let end_line = (start_line + visible_lines).min(lines.len());
+    let start_line = match app.preview_offset {
+        Some((selected, start)) if selected == app.selected => start.min(lines.len().saturating_sub(1)),
+        _ if app.wrap_lines => {
+            let width = inner.width.saturating_sub(5) as usize;
+            wrapped_window_start(&lines, target_line, width, visible_lines)
+        }
+        _ => target_line.saturating_sub(half_visible),
+    };
+    app.preview_start_drawn.set(start_line);
+    draw_scrollbar(frame, area, lines.len(), start_line, visible_lines, theme);
+
+    let is_target = |line_num: usize| line_num == entry.line_num;
+    let rows = preview_rows(&lines, start_line, is_target, app.preview_hscroll, inner, app);
+    frame.render_widget(Paragraph::new(rows), inner);
+}
+
+/// Rows for lines `start..` of a file under their line numbers, filling
+/// `inner`. Lines wrap when wrapping is on and are otherwise cut at the
+/// edge after skipping `hscroll` characters. Lines for which `bright`
+/// holds (given the 1-based line number) stand out from the rest.
+fn preview_rows(
+    lines: &[&str],
+    start: usize,
+    bright: impl Fn(usize) -> bool,
+    hscroll: usize,
+    inner: Rect,
+    app: &App,
+) -> Vec<Line<'static>> {
+    let theme = &app.theme;
+    let height = inner.height as usize;
+    if app.wrap_lines {
+        return wrapped_preview_lines(lines, start, bright, inner.width.saturating_sub(5) as usize, height, theme);
+    }
 
-    let preview_lines: Vec<Line> = lines[start_line..end_line]
+    let max_width = inner.width.saturating_sub(7) as usize;
+    lines
         .iter()
         .enumerate()
+        .skip(start)
+        .take(height)
         .map(|(i, line)| {
-            let actual_line_num = start_line + i + 1;
-            let is_target = actual_line_num == entry.line_num;
-
-            let line_num_style = if is_target {
-                Style::default().fg(HIGHLIGHT)
-            } else {
-                Style::default().fg(DIM)
-            };
-
-            let content_style = if is_target {
-                Style::default().fg(Color::White)
-            } else {
-                Style::default().fg(DIM)
-            };
+            let line_num = i + 1;
+            let is_bright = bright(line_num);
+            let line_num_style = Style::default().fg(if is_bright { theme.highlight } else { theme.dim });
+            let content_style = Style::default().fg(if is_bright { theme.text } else { theme.dim });
 
-            let max_width = inner.width.saturating_sub(6) as usize;
-            let truncated: String = line.chars().take(max_width).collect();
+            let rest = line.char_indices().nth(hscroll).map_or("", |(i, _)| &line[i..]);
+            let truncated = fit_width(rest, max_width);
 
-            Line::from(vec![
-                Span::styled(format!("{:>4} ", actual_line_num), line_num_style),
-                Span::styled(truncated, content_style),
-            ])
+            let mut spans = vec![Span::styled(format!("{:>4} ", line_num), line_num_style)];
+            if hscroll > 0 && !line.is_empty() {
+                spans.push(Span::styled("‹", Style::default().fg(theme.accent)));
+            }
+            spans.push(Span::styled(truncated.to_string(), content_style));
+            if truncated.len() < rest.len() {
+                spans.push(Span::styled("›", Style::default().fg(theme.accent)));
+            }
+            Line::from(spans)
         })
-        .collect();
-
-    let paragraph = Paragraph::new(preview_lines);
-    frame.render_widget(paragraph, inner);
+        .collect()
 }
 
 fn draw_chat_response(frame: &mut Frame, area: Rect, app: &App) {
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
-        .padding(Padding::new(2, 2, 1, 1));
+    let theme = &app.theme;
+    let block = chat_response_block(theme);
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
+    app.chat_links_drawn.borrow_mut().clear();
+
+    if app.show_prompt {
+        draw_system_prompt(frame, inner, app);
+        return;
+    }
 
     if app.api_key.is_none() {
         let paragraph = Paragraph::new(Span::styled(
-            "OPENROUTER_API_KEY not found. Set it in ~/.env or environment.",
-            Style::default().fg(Color::Red),
+            format!(
+                "{} not found. Set it in ~/.env or environment and press {}, or use a local model with FINDER_PROVIDER=ollama.",
+                app.provider.key_var().unwrap_or_default(),
+                app.keymap.label(Action::ReloadKey)
+            ),
+            Style::default().fg(theme.error),
         ));
         frame.render_widget(paragraph, inner);
         return;
     }
 
-    let content = if app.chat_response.is_empty() && !app.chat_streaming {
+    let content = chat_content(app);
+
+    let is_placeholder = app.chat_response.is_empty() && app.chat_messages.is_empty();
+
+    if is_placeholder {
+        let paragraph = Paragraph::new(content)
+            .style(Style::default().fg(theme.dim))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(paragraph, inner);
+    } else {
+        let (styled_text, links) = chat_text(inner.width as usize, app);
+        let total = styled_text.lines.len();
+        let visible = app.chat_scroll..app.chat_scroll + inner.height as usize;
+        *app.chat_links_drawn.borrow_mut() = links
+            .into_iter()
+            .filter(|link| visible.contains(&link.row))
+            .map(|link| ChatLink {
+                row: inner.y as usize + link.row - app.chat_scroll,
+                columns: inner.x as usize + link.columns.start..inner.x as usize + link.columns.end,
+                url: link.url,
+            })
+            .collect();
+        // Already wrapped into rows, so the rows drawn are the rows counted.
+        let paragraph = Paragraph::new(styled_text).scroll((app.chat_scroll as u16, 0));
+        frame.render_widget(paragraph, inner);
+        draw_scrollbar(frame, area, total, app.chat_scroll, inner.height as usize, theme);
+    }
+}
+
+/// The rendered chat wrapped into rows, and where its links wrap to,
+/// re-rendered only when the chat or the width changed since the last frame.
+fn chat_text(width: usize, app: &App) -> (Text<'static>, Vec<ChatLink>) {
+    let mut cache = app.chat_render.borrow_mut();
+    if let Some(render) = cache.as_ref() {
+        if render.revision == app.chat_revision && render.width == width {
+            return (render.text.clone(), render.links.clone());
+        }
+    }
+
+    let (markdown_text, spans) = crate::markdown::render_with_links(&chat_content(app), width, &app.theme);
+    let text = if app.config.citations {
+        crate::markdown::highlight_citations(markdown_text, &app.theme)
+    } else {
+        markdown_text
+    };
+    let (text, links) = wrap_text(text, &spans, width);
+    *cache = Some(ChatRender { revision: app.chat_revision, width, text: text.clone(), links: links.clone() });
+    (text, links)
+}
+
+/// Splits every line of `text` into the rows `wrap_words` gives it,
+/// keeping each span's style, and places `links` on the rows and columns
+/// their text lands on. A link that wraps gets one entry per row.
+fn wrap_text(text: Text<'static>, links: &[LinkSpan], width: usize) -> (Text<'static>, Vec<ChatLink>) {
+    let mut rows = Vec::new();
+    let mut placed = Vec::new();
+    for (i, line) in text.lines.into_iter().enumerate() {
+        let chars: Vec<char> = line.spans.iter().flat_map(|s| s.content.chars()).collect();
+        let mut spans = Vec::new();
+        let mut start = 0;
+        for span in &line.spans {
+            let end = start + span.content.chars().count();
+            spans.push((start..end, span.style));
+            start = end;
+        }
+
+        for row in wrap_words(&chars, width) {
+            let column = |to: usize| chars[row.start..to].iter().map(|c| c.width().unwrap_or(0)).sum::<usize>();
+            for link in links.iter().filter(|l| l.line == i) {
+                let (from, to) = (link.chars.start.max(row.start), link.chars.end.min(row.end));
+                if from < to {
+                    placed.push(ChatLink { row: rows.len(), columns: column(from)..column(to), url: link.url.clone() });
+                }
+            }
+            let row_spans: Vec<Span<'static>> = spans
+                .iter()
+                .filter_map(|(range, style)| {
+                    let (from, to) = (range.start.max(row.start), range.end.min(row.end));
+                    (from < to).then(|| Span::styled(chars[from..to].iter().collect::<String>(), *style))
+                })
+                .collect();
+            let mut wrapped = Line::from(row_spans).style(line.style);
+            wrapped.alignment = line.alignment;
+            rows.push(wrapped);
+        }
+    }
+    (Text::from(rows), placed)
+}
+
+/// Furthest the chat response can scroll in a terminal of `area`, so a
+/// resize never leaves the pane scrolled past its content.
+pub fn chat_max_scroll(area: Rect, app: &App) -> usize {
+    let inner = chat_response_block(&app.theme).inner(chat_layout(area, app)[2]);
+    let (text, _) = chat_text(inner.width as usize, app);
+    text.lines.len().saturating_sub(inner.height as usize)
+}
+
+fn chat_content(app: &App) -> String {
+    if app.chat_response.is_empty() && !app.chat_streaming {
         if app.chat_messages.is_empty() {
             "Type your question and press Enter...".to_string()
         } else {
@@ -364,102 +1147,195 @@ fn draw_chat_response(frame: &mut Frame, area: Rect, app: &App) {
         format!("{}|", app.chat_response)
     } else {
         app.chat_response.clone()
-    };
+    }
+}
 
-    let is_placeholder = app.chat_response.is_empty() && app.chat_messages.is_empty();
+fn line_rows(line: &Line, width: usize) -> usize {
+    let chars: Vec<char> = line.spans.iter().flat_map(|s| s.content.chars()).collect();
+    wrap_words(&chars, width).len()
+}
 
-    if is_placeholder {
-        let paragraph = Paragraph::new(content)
-            .style(Style::default().fg(DIM))
-            .wrap(Wrap { trim: false });
-        frame.render_widget(paragraph, inner);
-    } else {
-        let markdown_text = crate::markdown::render(&content);
-        let styled_text = crate::markdown::highlight_citations(markdown_text);
-        let paragraph = Paragraph::new(styled_text)
-            .wrap(Wrap { trim: false })
-            .scroll((app.chat_scroll as u16, 0));
-        frame.render_widget(paragraph, inner);
+/// Scrollbar on the right border of a pane, drawn only when `total` items
+/// don't fit in `visible`.
+fn draw_scrollbar(frame: &mut Frame, area: Rect, total: usize, position: usize, visible: usize, theme: &Theme) {
+    if total <= visible {
+        return;
     }
+    let mut state = ScrollbarState::new(total.saturating_sub(visible))
+        .position(position)
+        .viewport_content_length(visible);
+    let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+        .begin_symbol(None)
+        .end_symbol(None)
+        .track_style(Style::default().fg(theme.dim))
+        .thumb_style(Style::default().fg(theme.accent));
+    frame.render_stateful_widget(scrollbar, area.inner(Margin { vertical: 1, horizontal: 0 }), &mut state);
+}
+
+/// Wrapped row of each heading in the chat response, for `[`/`]` jumps.
+pub fn chat_heading_rows(area: Rect, app: &App) -> Vec<usize> {
+    let width = chat_response_block(&app.theme).inner(chat_layout(area, app)[2]).width as usize;
+    let content = chat_content(app);
+    let (text, headings) = crate::markdown::render_with_headings(&content, width, &app.theme);
+
+    let mut rows = Vec::new();
+    let mut row = 0;
+    for (i, line) in text.lines.iter().enumerate() {
+        if headings.contains(&i) {
+            rows.push(row);
+        }
+        row += line_rows(line, width);
+    }
+    rows
+}
+
+fn draw_system_prompt(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
+    let mut lines = vec![
+        Line::from(Span::styled("System prompt (Ctrl+P to close)", Style::default().fg(theme.accent))),
+        Line::from(""),
+    ];
+    lines.extend(
+        app.system_prompt()
+            .lines()
+            .map(|l| Line::from(Span::styled(l.to_string(), Style::default().fg(theme.dim)))),
+    );
+
+    let paragraph = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .scroll((app.prompt_scroll as u16, 0));
+    frame.render_widget(paragraph, area);
+}
+
+fn match_marks(app: &App, entry: &SearchEntry) -> Vec<(u32, u8)> {
+    entry
+        .match_indices
+        .iter()
+        .enumerate()
+        .map(|(i, &idx)| {
+            let group = if app.config.multi_highlight {
+                entry.match_groups.get(i).copied().unwrap_or(0)
+            } else {
+                0
+            };
+            (idx, group)
+        })
+        .collect()
 }
 
-fn highlight_text(text: &str, indices: &[u32], base_style: Style) -> Vec<Span<'static>> {
-    let highlight_style = base_style.fg(HIGHLIGHT);
+fn highlight_text(text: &str, marks: &[(u32, u8)], base_style: Style, theme: &Theme) -> Vec<Span<'static>> {
+    // A theme without colors (like mono) marks matches bold and underlined
+    // instead, or they would look like the text around them.
+    let mark = |color: Color| match color {
+        Color::Reset => base_style.fg(color).add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+        _ => base_style.fg(color),
+    };
+    let style_for = |group: Option<u8>| match group {
+        Some(0) => mark(theme.highlight),
+        Some(g) => mark(theme.terms[(g as usize - 1) % theme.terms.len()]),
+        None => base_style,
+    };
     let chars: Vec<char> = text.chars().collect();
     let mut spans = Vec::new();
     let mut current = String::new();
-    let mut is_highlight = false;
+    let mut current_group: Option<u8> = None;
 
     for (i, &c) in chars.iter().enumerate() {
-        let should_highlight = indices.contains(&(i as u32));
+        let group = marks.iter().find(|&&(idx, _)| idx == i as u32).map(|&(_, g)| g);
 
-        if should_highlight != is_highlight {
+        if group != current_group {
             if !current.is_empty() {
-                let style = if is_highlight { highlight_style } else { base_style };
-                spans.push(Span::styled(current.clone(), style));
+                spans.push(Span::styled(current.clone(), style_for(current_group)));
                 current.clear();
             }
-            is_highlight = should_highlight;
+            current_group = group;
         }
         current.push(c);
     }
 
     if !current.is_empty() {
-        let style = if is_highlight { highlight_style } else { base_style };
-        spans.push(Span::styled(current, style));
+        spans.push(Span::styled(current, style_for(current_group)));
     }
 
     spans
 }
 
-fn draw_citations(frame: &mut Frame, app: &App) {
-    let area = frame.area();
-
-    let chunks = Layout::vertical([
+fn citations_layout(area: Rect) -> Rc<[Rect]> {
+    Layout::vertical([
         Constraint::Length(5),
         Constraint::Length(3),
         Constraint::Min(1),
         Constraint::Length(3),
     ])
-    .split(area);
+    .split(area)
+}
+
+fn citations_content_layout(area: Rect) -> Rc<[Rect]> {
+    Layout::horizontal([
+        Constraint::Percentage(40),
+        Constraint::Percentage(60),
+    ])
+    .split(area)
+}
+
+fn citations_preview_block() -> Block<'static> {
+    Block::default()
+        .borders(Borders::ALL)
+        .padding(Padding::new(2, 2, 1, 1))
+}
+
+pub fn in_citations_preview(area: Rect, col: u16, row: u16) -> bool {
+    citations_content_layout(citations_layout(area)[2])[1].contains(Position::new(col, row))
+}
+
+pub fn citations_preview_height(area: Rect) -> usize {
+    let preview = citations_content_layout(citations_layout(area)[2])[1];
+    citations_preview_block().inner(preview).height as usize
+}
+
+fn draw_citations(frame: &mut Frame, app: &App) {
+    let chunks = citations_layout(frame.area());
 
     draw_header(frame, chunks[0], app);
     draw_citations_input(frame, chunks[1], app);
     draw_citations_content(frame, chunks[2], app);
-    draw_citations_footer(frame, chunks[3]);
+    draw_citations_footer(frame, chunks[3], app);
 }
 
 fn draw_citations_content(frame: &mut Frame, area: Rect, app: &App) {
-    let chunks = Layout::horizontal([
-        Constraint::Percentage(40),
-        Constraint::Percentage(60),
-    ])
-    .split(area);
+    let chunks = citations_content_layout(area);
 
     draw_citations_list(frame, chunks[0], app);
     draw_citations_preview(frame, chunks[1], app);
 }
 
 fn draw_citations_input(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let text = format!("> {}_", app.citations_query);
     let paragraph = Paragraph::new(text)
-        .style(Style::default().fg(Color::White))
+        .style(Style::default().fg(theme.text))
         .block(block);
     frame.render_widget(paragraph, area);
 }
 
-fn draw_citations_list(frame: &mut Frame, area: Rect, app: &App) {
-    let block = Block::default()
+/// Border and padding shared by the citation and directory lists.
+fn picker_list_block() -> Block<'static> {
+    Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .padding(Padding::new(2, 2, 1, 1))
+}
+
+fn draw_citations_list(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
+    let block = picker_list_block()
+        .border_style(Style::default().fg(theme.dim))
         .title(format!(" Citations ({}) ", app.citations.len()))
-        .title_style(Style::default().fg(BLUE))
-        .padding(Padding::new(2, 2, 1, 1));
+        .title_style(Style::default().fg(theme.accent));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
@@ -476,17 +1352,13 @@ fn draw_citations_list(frame: &mut Frame, area: Rect, app: &App) {
         } else {
             "No matches"
         };
-        let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(DIM)));
+        let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(theme.dim)));
         frame.render_widget(paragraph, inner);
         return;
     }
 
     let visible_height = inner.height as usize / 2;
-    let skip = if app.citations_selected >= visible_height {
-        app.citations_selected - visible_height + 1
-    } else {
-        0
-    };
+    let skip = list_skip(app.citations_selected, visible_height);
     let items: Vec<ListItem> = citations
         .iter()
         .enumerate()
@@ -495,25 +1367,40 @@ fn draw_citations_list(frame: &mut Frame, area: Rect, app: &App) {
         .map(|(idx, citation)| {
             let is_selected = idx == app.citations_selected;
             let marker = if is_selected { ">" } else { " " };
-            let marker_style = Style::default().fg(BLUE);
+            let marker_style = Style::default().fg(theme.accent);
 
             let file_style = if is_selected {
                 Style::default()
-                    .fg(Color::White)
+                    .fg(theme.text)
                     .add_modifier(Modifier::BOLD)
             } else {
-                Style::default().fg(Color::White)
+                Style::default().fg(theme.text)
             };
 
-            let line_style = Style::default().fg(DIM);
+            let line_style = Style::default().fg(theme.dim);
+
+            let lines_cited = if citation.end > citation.line {
+                format!("{}-{}", citation.line, citation.end)
+            } else {
+                citation.line.to_string()
+            };
+            let mut spans = vec![Span::styled(marker, marker_style)];
+            if citation.valid {
+                spans.push(Span::styled(format!(" {}", citation.file), file_style));
+                spans.push(Span::styled(format!(":{}", lines_cited), line_style));
+            } else {
+                spans.push(Span::styled(
+                    format!(" {}:{}", citation.file, lines_cited),
+                    line_style.add_modifier(Modifier::CROSSED_OUT),
+                ));
+                spans.push(Span::styled(" ⚠ not found", Style::default().fg(theme.warning)));
+            }
 
+            let max_width = inner.width.saturating_sub(2) as usize;
+            let text = fit_width(&citation.text, max_width);
             let lines = vec![
-                Line::from(vec![
-                    Span::styled(marker, marker_style),
-                    Span::styled(format!(" {}", citation.file), file_style),
-                    Span::styled(format!(":{}", citation.line), line_style),
-                ]),
-                Line::from(""),
+                Line::from(spans),
+                Line::from(Span::styled(format!("  {}", text), Style::default().fg(theme.dim))),
             ];
 
             ListItem::new(lines)
@@ -525,10 +1412,8 @@ fn draw_citations_list(frame: &mut Frame, area: Rect, app: &App) {
 }
 
 fn draw_citations_preview(frame: &mut Frame, area: Rect, app: &App) {
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
-        .padding(Padding::new(2, 2, 1, 1));
+    let theme = &app.theme;
+    let block = citations_preview_block().border_style(Style::default().fg(theme.dim));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
@@ -540,73 +1425,65 @@ fn draw_citations_preview(frame: &mut Frame, area: Rect, app: &App) {
     };
 
     let Some(citation) = citations.get(app.citations_selected) else {
-        let paragraph = Paragraph::new(Span::styled("No preview", Style::default().fg(DIM)));
+        let paragraph = Paragraph::new(Span::styled("No preview", Style::default().fg(theme.dim)));
         frame.render_widget(paragraph, inner);
         return;
     };
 
-    let file_path = app.cwd.join(&citation.file);
-    let Ok(content) = std::fs::read_to_string(&file_path) else {
-        let paragraph = Paragraph::new(Span::styled("Cannot read file", Style::default().fg(DIM)));
-        frame.render_widget(paragraph, inner);
-        return;
+    let content = match app.previews.get(&app.resolve(&citation.file)) {
+        Preview::Ready(content) => content,
+        preview => {
+            let msg = preview_message(&preview, false);
+            let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(theme.dim)));
+            frame.render_widget(paragraph, inner);
+            return;
+        }
     };
 
     let lines: Vec<&str> = content.lines().collect();
-    let target_line = citation.line.saturating_sub(1);
     let visible_lines = inner.height as usize;
-    let half_visible = visible_lines / 2;
-
-    let start_line = target_line.saturating_sub(half_visible);
-    let end_line = (start_line + visible_lines).min(lines.len());
-
-    let preview_lines: Vec<Line> = lines[start_line..end_line]
-        .iter()
-        .enumerate()
-        .map(|(i, line)| {
-            let actual_line_num = start_line + i + 1;
-            let is_target = actual_line_num == citation.line;
-
-            let line_num_style = if is_target {
-                Style::default().fg(HIGHLIGHT)
-            } else {
-                Style::default().fg(DIM)
-            };
-
-            let content_style = if is_target {
-                Style::default().fg(Color::White)
-            } else {
-                Style::default().fg(DIM)
-            };
+    let max_start = lines.len().saturating_sub(visible_lines);
 
-            let max_width = inner.width.saturating_sub(6) as usize;
-            let truncated: String = line.chars().take(max_width).collect();
+    let start_line = app
+        .citations_preview_offset
+        .unwrap_or_else(|| preview_window_start(citation.line, visible_lines, lines.len()))
+        .min(max_start);
+    draw_scrollbar(frame, area, lines.len(), start_line, visible_lines, theme);
 
-            Line::from(vec![
-                Span::styled(format!("{:>4} ", actual_line_num), line_num_style),
-                Span::styled(truncated, content_style),
-            ])
-        })
-        .collect();
-
-    let paragraph = Paragraph::new(preview_lines);
-    frame.render_widget(paragraph, inner);
+    let is_target = |line_num: usize| (citation.line..=citation.end).contains(&line_num);
+    let rows = preview_rows(&lines, start_line, is_target, 0, inner, app);
+    frame.render_widget(Paragraph::new(rows), inner);
 }
 
-fn draw_citations_footer(frame: &mut Frame, area: Rect) {
+fn draw_citations_footer(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
+    let status_active = app.status_message.as_ref().is_some_and(|(_, instant)| {
+        instant.elapsed().as_secs() < 3
+    });
+
+    if status_active {
+        let msg = app.status_message.as_ref().map(|(m, _)| m.as_str()).unwrap_or("");
+        let line = Line::from(vec![
+            Span::styled("⚠ ", Style::default().fg(theme.warning)),
+            Span::styled(msg, Style::default().fg(theme.warning)),
+        ]);
+        frame.render_widget(Paragraph::new(line), inner);
+        return;
+    }
+
     let hints = vec![
-        Span::styled("[Enter]", Style::default().fg(BLUE)),
-        Span::styled(" open  ", Style::default().fg(DIM)),
-        Span::styled("[Esc]", Style::default().fg(BLUE)),
-        Span::styled(" back", Style::default().fg(DIM)),
+        Span::styled("[Enter]", Style::default().fg(theme.accent)),
+        Span::styled(" open  ", Style::default().fg(theme.dim)),
+        Span::styled("[Esc]", Style::default().fg(theme.accent)),
+        Span::styled(" back", Style::default().fg(theme.dim)),
     ];
 
     let paragraph = Paragraph::new(Line::from(hints));
@@ -614,31 +1491,40 @@ fn draw_citations_footer(frame: &mut Frame, area: Rect) {
 }
 
 fn draw_chat_footer(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
     let hints: Vec<Span> = if app.chat_streaming {
-        vec![
-            Span::styled("streaming... ", Style::default().fg(BLUE)),
-            Span::styled("[Ctrl+C]", Style::default().fg(DIM)),
-            Span::styled(" cancel", Style::default().fg(DIM)),
-        ]
+        let mut hints = vec![
+            Span::styled("streaming... ", Style::default().fg(theme.accent)),
+            Span::styled("[Ctrl+C]", Style::default().fg(theme.dim)),
+            Span::styled(" cancel", Style::default().fg(theme.dim)),
+        ];
+        if !app.citations.is_empty() {
+            hints.push(Span::styled(
+                format!("  [{}]", app.keymap.label(Action::Citations)),
+                Style::default().fg(theme.highlight),
+            ));
+            hints.push(Span::styled(format!(" {} citations", app.citations.len()), Style::default().fg(theme.dim)));
+        }
+        hints
     } else if !app.citations.is_empty() {
         vec![
-            Span::styled("[Esc]", Style::default().fg(BLUE)),
-            Span::styled(" back  ", Style::default().fg(DIM)),
-            Span::styled("[Alt+c]", Style::default().fg(HIGHLIGHT)),
-            Span::styled(format!(" {} citations", app.citations.len()), Style::default().fg(DIM)),
+            Span::styled("[Esc]", Style::default().fg(theme.accent)),
+            Span::styled(" back  ", Style::default().fg(theme.dim)),
+            Span::styled(format!("[{}]", app.keymap.label(Action::Citations)), Style::default().fg(theme.highlight)),
+            Span::styled(format!(" {} citations", app.citations.len()), Style::default().fg(theme.dim)),
         ]
     } else {
         vec![
-            Span::styled("[Esc]", Style::default().fg(BLUE)),
-            Span::styled(" back", Style::default().fg(DIM)),
+            Span::styled("[Esc]", Style::default().fg(theme.accent)),
+            Span::styled(" back", Style::default().fg(theme.dim)),
         ]
     };
 
@@ -647,76 +1533,70 @@ fn draw_chat_footer(frame: &mut Frame, area: Rect, app: &App) {
 }
 
 fn draw_directory_picker(frame: &mut Frame, app: &App) {
-    let area = frame.area();
-
-    let chunks = Layout::vertical([
-        Constraint::Length(5),
-        Constraint::Length(3),
-        Constraint::Min(1),
-        Constraint::Length(3),
-    ])
-    .split(area);
+    // Same rows as the citations view: header, input, lists, footer.
+    let chunks = citations_layout(frame.area());
 
     draw_header(frame, chunks[0], app);
     draw_dir_input(frame, chunks[1], app);
     draw_dir_content(frame, chunks[2], app);
-    draw_dir_footer(frame, chunks[3]);
+    draw_dir_footer(frame, chunks[3], app);
 }
 
 fn draw_dir_input(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let text = format!("> {}_", app.dir_query);
     let paragraph = Paragraph::new(text)
-        .style(Style::default().fg(Color::White))
+        .style(Style::default().fg(theme.text))
         .block(block);
     frame.render_widget(paragraph, area);
 }
 
 fn draw_dir_content(frame: &mut Frame, area: Rect, app: &App) {
-    let chunks = Layout::horizontal([
-        Constraint::Percentage(40),
-        Constraint::Percentage(60),
-    ])
-    .split(area);
+    let chunks = citations_content_layout(area);
 
     draw_dir_list(frame, chunks[0], app);
     draw_dir_preview(frame, chunks[1], app);
 }
 
 fn draw_dir_list(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let dirs = app.dir_list();
 
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
-        .title(format!(" Directories ({}) ", dirs.len()))
-        .title_style(Style::default().fg(BLUE))
-        .padding(Padding::new(2, 2, 1, 1));
+    let count = if app.dir_scanning() { "scanning…".to_string() } else { dirs.len().to_string() };
+    let mut block = picker_list_block()
+        .border_style(Style::default().fg(theme.dim))
+        .title(format!(" Directories ({}) ", count))
+        .title_style(Style::default().fg(theme.accent));
+    if app.dir_truncated {
+        block = block.title_bottom(Line::styled(
+            format!(" showing first {} dirs, pick a closer directory to see the rest ", app.config.dir_limit),
+            Style::default().fg(theme.dim),
+        ));
+    }
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
     if dirs.is_empty() {
-        let msg = if app.dir_query.is_empty() {
+        let msg = if app.dir_scanning() {
+            "Scanning..."
+        } else if app.dir_query.is_empty() {
             "No subdirectories"
         } else {
             "No matches"
         };
-        let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(DIM)));
+        let paragraph = Paragraph::new(Span::styled(msg, Style::default().fg(theme.dim)));
         frame.render_widget(paragraph, inner);
         return;
     }
 
     let visible_height = inner.height as usize;
-    let skip = if app.dir_selected >= visible_height {
-        app.dir_selected - visible_height + 1
-    } else {
-        0
-    };
+    let skip = list_skip(app.dir_selected, visible_height);
     let items: Vec<ListItem> = dirs
         .iter()
         .enumerate()
@@ -725,19 +1605,19 @@ fn draw_dir_list(frame: &mut Frame, area: Rect, app: &App) {
         .map(|(idx, dir)| {
             let is_selected = idx == app.dir_selected;
             let marker = if is_selected { ">" } else { " " };
-            let marker_style = Style::default().fg(BLUE);
+            let marker_style = Style::default().fg(theme.accent);
 
             let dir_style = if is_selected {
                 Style::default()
-                    .fg(Color::White)
+                    .fg(theme.text)
                     .add_modifier(Modifier::BOLD)
             } else {
-                Style::default().fg(Color::White)
+                Style::default().fg(theme.text)
             };
 
             let dir_str = dir.to_string_lossy();
             let display = if dir_str.starts_with("..") {
-                let full_path = app.original_cwd.join(dir);
+                let full_path = app.cwd.join(dir);
                 if let Ok(resolved) = full_path.canonicalize() {
                     if let Some(name) = resolved.file_name() {
                         format!("{} ({})", dir_str, name.to_string_lossy())
@@ -762,12 +1642,28 @@ fn draw_dir_list(frame: &mut Frame, area: Rect, app: &App) {
     frame.render_widget(list, inner);
 }
 
+/// `512 B`, `2.1 KB`, `3.4 MB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
 fn draw_dir_preview(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .title(" Contents ")
-        .title_style(Style::default().fg(BLUE))
+        .title_style(Style::default().fg(theme.accent))
         .padding(Padding::new(2, 2, 1, 1));
 
     let inner = block.inner(area);
@@ -775,98 +1671,130 @@ fn draw_dir_preview(frame: &mut Frame, area: Rect, app: &App) {
 
     let dirs = app.dir_list();
     let Some(selected_dir) = dirs.get(app.dir_selected) else {
-        let paragraph = Paragraph::new(Span::styled("No directory selected", Style::default().fg(DIM)));
+        let paragraph = Paragraph::new(Span::styled("No directory selected", Style::default().fg(theme.dim)));
         frame.render_widget(paragraph, inner);
         return;
     };
 
-    let full_path = app.original_cwd.join(selected_dir);
+    let full_path = app.cwd.join(selected_dir);
     let full_path = full_path.canonicalize().unwrap_or(full_path);
+    let Some(preview) = app.dir_previews.get(&full_path) else {
+        let paragraph = Paragraph::new(Span::styled("loading…", Style::default().fg(theme.dim)));
+        frame.render_widget(paragraph, inner);
+        return;
+    };
 
-    let mut entries: Vec<String> = Vec::new();
-    if let Ok(read_dir) = std::fs::read_dir(&full_path) {
-        for entry in read_dir.flatten() {
-            let name = entry.file_name().to_string_lossy().to_string();
-            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
-            if is_dir {
-                entries.push(format!("{}/", name));
-            } else {
-                entries.push(name);
-            }
-        }
-    }
-    entries.sort();
-
-    if entries.is_empty() {
-        let paragraph = Paragraph::new(Span::styled("(empty)", Style::default().fg(DIM)));
+    if preview.entries.is_empty() {
+        let paragraph = Paragraph::new(Span::styled("(empty)", Style::default().fg(theme.dim)));
         frame.render_widget(paragraph, inner);
         return;
     }
 
-    let visible_height = inner.height as usize;
-    let lines: Vec<Line> = entries
-        .iter()
-        .take(visible_height)
-        .map(|entry| {
-            let style = if entry.ends_with('/') {
-                Style::default().fg(BLUE)
-            } else if entry.ends_with(".md") {
-                Style::default().fg(HIGHLIGHT)
-            } else {
-                Style::default().fg(DIM)
-            };
-            Line::from(Span::styled(entry.clone(), style))
-        })
-        .collect();
+    let dim = Style::default().fg(theme.dim);
+    let plus = if preview.capped { "+" } else { "" };
+    let mut text = vec![Line::from(Span::styled(
+        format!("{}{} indexable file{}", preview.indexable, plus, if preview.indexable == 1 { "" } else { "s" }),
+        Style::default().fg(theme.text),
+    ))];
+    if let Some((name, modified)) = &preview.newest {
+        text.push(Line::from(Span::styled(format!("newest: {}, {}", name, util::ago(*modified)), dim)));
+    }
+    text.push(Line::from(""));
 
-    let more = if entries.len() > visible_height {
-        format!("\n... and {} more", entries.len() - visible_height)
-    } else {
-        String::new()
-    };
+    let visible_height = (inner.height as usize).saturating_sub(text.len());
+    let width = inner.width as usize;
+    for entry in preview.entries.iter().take(visible_height) {
+        let Some(size) = entry.size else {
+            text.push(Line::from(Span::styled(format!("{}/", entry.name), Style::default().fg(theme.accent))));
+            continue;
+        };
+        let style = if entry.indexed { Style::default().fg(theme.highlight) } else { dim };
+        let details = match entry.modified {
+            Some(modified) => format!("  {} · {}", human_size(size), util::ago(modified)),
+            None => format!("  {}", human_size(size)),
+        };
+        let room = width.saturating_sub(details.width());
+        let name = fit_width(&entry.name, room);
+        let pad = room.saturating_sub(name.width());
+        text.push(Line::from(vec![
+            Span::styled(format!("{}{}", name, " ".repeat(pad)), style),
+            Span::styled(details, dim),
+        ]));
+    }
 
-    let mut text: Vec<Line> = lines;
-    if !more.is_empty() {
-        text.push(Line::from(Span::styled(more, Style::default().fg(DIM))));
+    if preview.entries.len() > visible_height {
+        text.push(Line::from(Span::styled(
+            format!("\n... and {} more", preview.entries.len() - visible_height),
+            dim,
+        )));
     }
 
     let paragraph = Paragraph::new(text);
     frame.render_widget(paragraph, inner);
 }
 
-fn draw_dir_footer(frame: &mut Frame, area: Rect) {
+fn draw_dir_footer(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
     let hints = vec![
-        Span::styled("[Enter]", Style::default().fg(BLUE)),
-        Span::styled(" select  ", Style::default().fg(DIM)),
-        Span::styled("[Esc]", Style::default().fg(BLUE)),
-        Span::styled(" cancel  ", Style::default().fg(DIM)),
-        Span::styled("[Ctrl+O]", Style::default().fg(HIGHLIGHT)),
-        Span::styled(" change dir", Style::default().fg(DIM)),
+        Span::styled("[Enter]", Style::default().fg(theme.accent)),
+        Span::styled(" select  ", Style::default().fg(theme.dim)),
+        Span::styled("[Esc]", Style::default().fg(theme.accent)),
+        Span::styled(" cancel  ", Style::default().fg(theme.dim)),
+        Span::styled(format!("[{}]", app.keymap.label(Action::DirectoryPicker)), Style::default().fg(theme.highlight)),
+        Span::styled(" change dir", Style::default().fg(theme.dim)),
     ];
 
     let paragraph = Paragraph::new(Line::from(hints));
     frame.render_widget(paragraph, inner);
 }
 
-fn draw_quick_answer(frame: &mut Frame, app: &App) {
-    let area = frame.area();
+fn quick_layout(area: Rect, app: &App) -> Rc<[Rect]> {
     let input_height = calc_input_height(app.quick_query.len(), area.width);
-
-    let chunks = Layout::vertical([
+    Layout::vertical([
         Constraint::Length(5),
         Constraint::Length(input_height),
         Constraint::Min(1),
         Constraint::Length(3),
     ])
+    .split(area)
+}
+
+/// The response pane and, once an answer is in, the sources panel below it.
+fn quick_response_layout(area: Rect, app: &App) -> (Rect, Option<Rect>) {
+    let has_sources = !app.quick_sources.is_empty();
+    if !has_sources || app.quick_streaming || app.quick_response.is_empty() {
+        return (area, None);
+    }
+
+    let sources_height = if app.quick_sources_expanded {
+        (app.quick_sources.len().min(QUICK_SOURCES_VISIBLE) + 2) as u16
+    } else {
+        3
+    };
+    let chunks = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(sources_height),
+    ])
     .split(area);
+    (chunks[0], Some(chunks[1]))
+}
+
+fn quick_sources_block() -> Block<'static> {
+    Block::default()
+        .borders(Borders::ALL)
+        .padding(Padding::horizontal(1))
+}
+
+fn draw_quick_answer(frame: &mut Frame, app: &App) {
+    let chunks = quick_layout(frame.area(), app);
 
     draw_header(frame, chunks[0], app);
     draw_quick_input(frame, chunks[1], app);
@@ -875,17 +1803,18 @@ fn draw_quick_answer(frame: &mut Frame, app: &App) {
 }
 
 fn draw_quick_input(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
-    let prefix = Span::styled("@ ", Style::default().fg(HIGHLIGHT));
-    let query = Span::styled(&app.quick_query, Style::default().fg(Color::White));
-    let cursor = Span::styled("_", Style::default().fg(Color::White));
+    let prefix = Span::styled("@ ", Style::default().fg(theme.highlight));
+    let query = Span::styled(&app.quick_query, Style::default().fg(theme.text));
+    let cursor = Span::styled("_", Style::default().fg(theme.text));
 
     let line = Line::from(vec![prefix, query, cursor]);
     let paragraph = Paragraph::new(line).wrap(Wrap { trim: false });
@@ -893,42 +1822,30 @@ fn draw_quick_input(frame: &mut Frame, area: Rect, app: &App) {
 }
 
 fn draw_quick_response(frame: &mut Frame, area: Rect, app: &App) {
-    let has_sources = !app.quick_sources.is_empty();
-    let show_sources = has_sources && !app.quick_streaming && !app.quick_response.is_empty();
-
-    let response_area;
-    let sources_area;
-
-    if show_sources {
-        let sources_height = if app.quick_sources_expanded {
-            (app.quick_sources.len().min(5) + 2) as u16
-        } else {
-            3
-        };
-        let chunks = Layout::vertical([
-            Constraint::Min(1),
-            Constraint::Length(sources_height),
-        ])
-        .split(area);
-        response_area = chunks[0];
-        sources_area = Some(chunks[1]);
-    } else {
-        response_area = area;
-        sources_area = None;
-    }
+    let theme = &app.theme;
+    let (response_area, sources_area) = quick_response_layout(area, app);
 
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::new(2, 2, 1, 1));
 
     let inner = block.inner(response_area);
     frame.render_widget(block, response_area);
 
+    if app.show_prompt {
+        draw_system_prompt(frame, inner, app);
+        return;
+    }
+
     if app.api_key.is_none() {
         let paragraph = Paragraph::new(Span::styled(
-            "OPENROUTER_API_KEY not found",
-            Style::default().fg(Color::Red),
+            format!(
+                "{} not found ({} reloads it)",
+                app.provider.key_var().unwrap_or_default(),
+                app.keymap.label(Action::ReloadKey)
+            ),
+            Style::default().fg(theme.error),
         ));
         frame.render_widget(paragraph, inner);
         return;
@@ -938,7 +1855,7 @@ fn draw_quick_response(frame: &mut Frame, area: Rect, app: &App) {
 
     if is_placeholder {
         let paragraph = Paragraph::new("Press Enter to ask...")
-            .style(Style::default().fg(DIM))
+            .style(Style::default().fg(theme.dim))
             .wrap(Wrap { trim: false });
         frame.render_widget(paragraph, inner);
     } else {
@@ -947,7 +1864,7 @@ fn draw_quick_response(frame: &mut Frame, area: Rect, app: &App) {
         } else {
             app.quick_response.clone()
         };
-        let markdown_text = crate::markdown::render(&content);
+        let markdown_text = crate::markdown::render(&content, inner.width as usize, &app.theme);
         let paragraph = Paragraph::new(markdown_text)
             .wrap(Wrap { trim: false });
         frame.render_widget(paragraph, inner);
@@ -959,45 +1876,43 @@ fn draw_quick_response(frame: &mut Frame, area: Rect, app: &App) {
 }
 
 fn draw_quick_sources(frame: &mut Frame, area: Rect, app: &App) {
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
-        .padding(Padding::horizontal(1));
+    let theme = &app.theme;
+    let block = quick_sources_block().border_style(Style::default().fg(theme.dim));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
     if app.quick_sources_expanded {
-        let skip = if app.quick_sources_selected >= 5 {
-            app.quick_sources_selected - 4
-        } else {
-            0
-        };
+        let skip = list_skip(app.quick_sources_selected, QUICK_SOURCES_VISIBLE);
         let items: Vec<ListItem> = app
             .quick_sources
             .iter()
             .enumerate()
             .skip(skip)
-            .take(5)
+            .take(QUICK_SOURCES_VISIBLE)
             .map(|(idx, chunk)| {
                 let is_selected = idx == app.quick_sources_selected;
                 let marker = if is_selected { ">" } else { " " };
-                let marker_style = Style::default().fg(BLUE);
+                let marker_style = Style::default().fg(theme.accent);
 
                 let file_style = if is_selected {
-                    Style::default().fg(Color::White).add_modifier(Modifier::BOLD)
+                    Style::default().fg(theme.text).add_modifier(Modifier::BOLD)
                 } else {
-                    Style::default().fg(Color::White)
+                    Style::default().fg(theme.text)
                 };
 
-                let preview: String = chunk.content.chars().take(40).collect();
-                let suffix = if chunk.content.len() > 40 { "..." } else { "" };
+                let score = format!(" {:>5.1}", chunk.score);
+                let location = format!(" {}:{}  ", chunk.file, chunk.line);
+                let used = score.chars().count() + location.chars().count() + 1;
+                let room = (inner.width as usize).saturating_sub(used).max(10);
 
-                ListItem::new(Line::from(vec![
+                let mut spans = vec![
                     Span::styled(marker, marker_style),
-                    Span::styled(format!(" {}:{}", chunk.file, chunk.line), file_style),
-                    Span::styled(format!("  {}{}", preview, suffix), Style::default().fg(DIM)),
-                ]))
+                    Span::styled(score, Style::default().fg(theme.accent)),
+                    Span::styled(location, file_style),
+                ];
+                spans.extend(source_excerpt(chunk, room, &app.theme));
+                ListItem::new(Line::from(spans))
             })
             .collect();
 
@@ -1005,72 +1920,206 @@ fn draw_quick_sources(frame: &mut Frame, area: Rect, app: &App) {
         frame.render_widget(list, inner);
     } else {
         let text = Line::from(vec![
-            Span::styled("Sources ", Style::default().fg(DIM)),
-            Span::styled(format!("({} chunks)", app.quick_sources.len()), Style::default().fg(BLUE)),
-            Span::styled("  [Tab] expand", Style::default().fg(DIM)),
+            Span::styled("Sources ", Style::default().fg(theme.dim)),
+            Span::styled(format!("({} chunks)", app.quick_sources.len()), Style::default().fg(theme.accent)),
+            Span::styled(
+                if app.quick_sources_relaxed { "  fuzzy matches added" } else { "" },
+                Style::default().fg(theme.warning),
+            ),
+            Span::styled("  [Tab] expand", Style::default().fg(theme.dim)),
         ]);
         let paragraph = Paragraph::new(text);
         frame.render_widget(paragraph, inner);
     }
 }
 
+/// The chunk's snippet with matched terms highlighted, or the start of its
+/// content when the match had no snippet, cut to `room` columns.
+fn source_excerpt(chunk: &crate::rag::RagChunk, room: usize, theme: &Theme) -> Vec<Span<'static>> {
+    let dim = Style::default().fg(theme.dim);
+    let text = if chunk.snippet.is_empty() { &chunk.content } else { &chunk.snippet };
+
+    let marks: Vec<(u32, u8)> = text
+        .char_indices()
+        .enumerate()
+        .filter(|(_, (byte, _))| chunk.highlights.iter().any(|r| r.contains(byte)))
+        .map(|(i, _)| (i as u32, 0))
+        .collect();
+
+    let mut shown = fit_width(text, room).to_string();
+    if shown.len() < text.len() {
+        shown.pop();
+        shown.push('…');
+    }
+    highlight_text(&shown, &marks, dim, theme)
+}
+
 fn draw_quick_footer(frame: &mut Frame, area: Rect, app: &App) {
+    let theme = &app.theme;
     let block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(DIM))
+        .border_style(Style::default().fg(theme.dim))
         .padding(Padding::horizontal(1));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
-    let status_active = app.status_message.as_ref().map_or(false, |(_, instant)| {
+    let status_active = app.status_message.as_ref().is_some_and(|(_, instant)| {
         instant.elapsed().as_secs() < 3
     });
 
-    let hints: Vec<Span> = if status_active {
+    let hints: Vec<Span> = if app.confirm_rebuild_pending {
+        vec![
+            Span::styled("Rebuild index? ", Style::default().fg(theme.warning)),
+            Span::styled("[y]", Style::default().fg(theme.accent)),
+            Span::styled(" yes  ", Style::default().fg(theme.dim)),
+            Span::styled("[any key]", Style::default().fg(theme.accent)),
+            Span::styled(" cancel", Style::default().fg(theme.dim)),
+        ]
+    } else if status_active {
         let msg = app.status_message.as_ref().map(|(m, _)| m.as_str()).unwrap_or("");
         vec![
-            Span::styled("✓ ", Style::default().fg(Color::Green)),
-            Span::styled(msg, Style::default().fg(Color::Green)),
+            Span::styled("✓ ", Style::default().fg(theme.success)),
+            Span::styled(msg, Style::default().fg(theme.success)),
         ]
     } else if app.quick_streaming {
         vec![
-            Span::styled("streaming... ", Style::default().fg(BLUE)),
-            Span::styled("[Ctrl+C]", Style::default().fg(DIM)),
-            Span::styled(" cancel", Style::default().fg(DIM)),
+            Span::styled("streaming... ", Style::default().fg(theme.accent)),
+            Span::styled("[Ctrl+C]", Style::default().fg(theme.dim)),
+            Span::styled(" cancel", Style::default().fg(theme.dim)),
         ]
     } else if app.quick_sources_expanded {
         vec![
-            Span::styled("[Enter]", Style::default().fg(BLUE)),
-            Span::styled(" open  ", Style::default().fg(DIM)),
-            Span::styled("[Tab]", Style::default().fg(BLUE)),
-            Span::styled(" collapse  ", Style::default().fg(DIM)),
-            Span::styled("[Esc]", Style::default().fg(BLUE)),
-            Span::styled(" back", Style::default().fg(DIM)),
+            Span::styled("[Enter]", Style::default().fg(theme.accent)),
+            Span::styled(" open  ", Style::default().fg(theme.dim)),
+            Span::styled("[Tab]", Style::default().fg(theme.accent)),
+            Span::styled(" collapse  ", Style::default().fg(theme.dim)),
+            Span::styled("[Esc]", Style::default().fg(theme.accent)),
+            Span::styled(" back", Style::default().fg(theme.dim)),
         ]
     } else if !app.quick_history.is_empty() {
         vec![
-            Span::styled(format!("follow-up ({})  ", app.quick_history.len()), Style::default().fg(Color::Yellow)),
-            Span::styled("[Enter]", Style::default().fg(BLUE)),
-            Span::styled(" ask  ", Style::default().fg(DIM)),
-            Span::styled("[Ctrl+N]", Style::default().fg(HIGHLIGHT)),
-            Span::styled(" new  ", Style::default().fg(DIM)),
-            Span::styled("[Esc]", Style::default().fg(BLUE)),
-            Span::styled(" back", Style::default().fg(DIM)),
+            Span::styled(format!("follow-up ({})  ", app.quick_history.len()), Style::default().fg(theme.warning)),
+            Span::styled("[Enter]", Style::default().fg(theme.accent)),
+            Span::styled(" ask  ", Style::default().fg(theme.dim)),
+            Span::styled("[Tab]", Style::default().fg(theme.accent)),
+            Span::styled(" sources  ", Style::default().fg(theme.dim)),
+            Span::styled("[Ctrl+F]", Style::default().fg(theme.accent)),
+            Span::styled(" full chat  ", Style::default().fg(theme.dim)),
+            Span::styled("[Ctrl+N]", Style::default().fg(theme.highlight)),
+            Span::styled(" new  ", Style::default().fg(theme.dim)),
+            Span::styled("[Esc]", Style::default().fg(theme.accent)),
+            Span::styled(" back", Style::default().fg(theme.dim)),
         ]
     } else {
         vec![
-            Span::styled("[Enter]", Style::default().fg(BLUE)),
-            Span::styled(" ask  ", Style::default().fg(DIM)),
-            Span::styled("[Tab]", Style::default().fg(BLUE)),
-            Span::styled(" sources  ", Style::default().fg(DIM)),
-            Span::styled("[Ctrl+R]", Style::default().fg(HIGHLIGHT)),
-            Span::styled(" rebuild  ", Style::default().fg(DIM)),
-            Span::styled("[Esc]", Style::default().fg(BLUE)),
-            Span::styled(" back", Style::default().fg(DIM)),
+            Span::styled("[Enter]", Style::default().fg(theme.accent)),
+            Span::styled(" ask  ", Style::default().fg(theme.dim)),
+            Span::styled("[Tab]", Style::default().fg(theme.accent)),
+            Span::styled(" sources  ", Style::default().fg(theme.dim)),
+            Span::styled("[Ctrl+R]", Style::default().fg(theme.highlight)),
+            Span::styled(" rebuild  ", Style::default().fg(theme.dim)),
+            Span::styled("[Esc]", Style::default().fg(theme.accent)),
+            Span::styled(" back", Style::default().fg(theme.dim)),
         ]
     };
 
     let paragraph = Paragraph::new(Line::from(hints));
     frame.render_widget(paragraph, inner);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mono_matches_stand_out_without_color() {
+        let spans = highlight_text("find me", &[(0, 0), (5, 1)], Style::default(), &Theme::MONO);
+        let marked: Vec<&str> = spans
+            .iter()
+            .filter(|s| s.style.add_modifier.contains(Modifier::BOLD | Modifier::UNDERLINED))
+            .map(|s| s.content.as_ref())
+            .collect();
+        assert_eq!(marked, vec!["f", "m"]);
+    }
+
+    #[test]
+    fn wraps_wide_characters_by_display_width() {
+        let chars: Vec<char> = "漢字漢字漢字".chars().collect();
+        assert_eq!(wrap_words(&chars, 5), vec![0..2, 2..4, 4..6]);
+
+        let chars: Vec<char> = "ab cd ef".chars().collect();
+        assert_eq!(wrap_words(&chars, 4), vec![0..3, 3..6, 6..8]);
+    }
+
+    #[test]
+    fn chat_rows_are_counted_as_drawn() {
+        let (text, _) = wrap_text(Text::from("漢".repeat(100)), &[], 50);
+        assert_eq!(text.lines.len(), 4);
+        assert!(text.lines.iter().all(|line| line.width() <= 50));
+
+        let styled = Text::from(Line::from(vec![Span::raw("word "), Span::styled("bold word", Style::default().add_modifier(Modifier::BOLD))]));
+        let (rows, _) = wrap_text(styled, &[], 6);
+        let contents: Vec<Vec<&str>> = rows.lines.iter().map(|l| l.spans.iter().map(|s| s.content.as_ref()).collect()).collect();
+        assert_eq!(contents, vec![vec!["word "], vec!["bold "], vec!["word"]]);
+        assert!(rows.lines[2].spans[0].style.add_modifier.contains(Modifier::BOLD));
+    }
+
+    #[test]
+    fn links_land_on_the_rows_they_are_drawn_on() {
+        let (text, spans) = crate::markdown::render_with_links("See the [setup guide](docs/setup.md) first", 14, &Theme::DARK);
+        let (_, links) = wrap_text(text, &spans, 14);
+
+        // "See the setup " then "guide first": the link wraps onto two rows.
+        let cells: Vec<(usize, Range<usize>)> = links.iter().map(|l| (l.row, l.columns.clone())).collect();
+        assert_eq!(cells, vec![(0, 8..14), (1, 0..5)]);
+        assert!(links.iter().all(|l| l.url == "docs/setup.md"));
+    }
+
+    #[test]
+    fn links_after_wide_lines_keep_their_row() {
+        let (text, spans) = crate::markdown::render_with_links("漢字漢字漢字漢字\n\nSee [it](https://example.com)", 8, &Theme::DARK);
+        let (rows, links) = wrap_text(text, &spans, 8);
+
+        // Two rows of wide characters, the blank line, then the link.
+        assert_eq!(rows.lines.len(), 4);
+        assert_eq!(links.iter().map(|l| (l.row, l.columns.clone())).collect::<Vec<_>>(), vec![(3, 4..6)]);
+    }
+
+    #[test]
+    fn moving_past_the_last_visible_result_scrolls_by_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = tempfile::tempdir().unwrap();
+        let mut app = App::with_dirs(dir.path().to_path_buf(), crate::config::UserDirs::under(state.path()));
+        app.inline_context = false;
+        app.wrap_lines = false;
+        app.results = (1..=10)
+            .map(|line_num| SearchEntry {
+                file: "notes.md".to_string(),
+                line_num,
+                content: format!("line {}", line_num),
+                match_indices: Vec::new(),
+                match_groups: Vec::new(),
+                rank: 0,
+                lang: None,
+            })
+            .collect();
+        // Three rows per result: three results fit.
+        let inner = Rect::new(0, 0, 40, 9);
+
+        for _ in 0..2 {
+            app.on_down();
+        }
+        assert_eq!((app.selected, results_skip(&app, inner)), (2, 0));
+
+        app.on_down();
+        assert_eq!((app.selected, results_skip(&app, inner)), (3, 1));
+        app.on_down();
+        assert_eq!((app.selected, results_skip(&app, inner)), (4, 2));
+
+        for _ in 0..20 {
+            app.on_down();
+        }
+        assert_eq!((app.selected, results_skip(&app, inner)), (9, 7));
+    }
+}
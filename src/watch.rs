@@ -0,0 +1,127 @@
+use crate::rag::SIDECAR_DIR;
+use crate::search;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+/// Quiet time after the last event before a change is reported, so the
+/// several writes an editor makes per save arrive as one notification.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// What changed on disk since the last check.
+pub enum Changes {
+    /// Created, edited or removed paths: files with a tracked extension,
+    /// directories, and removed paths of either kind. Paths the ignore
+    /// files exclude from indexing are left out.
+    Paths(Vec<PathBuf>),
+    /// Events were lost, so anything may have changed.
+    Unknown,
+}
+
+/// Watches a directory with the platform's file notifications.
+pub struct Watcher {
+    rx: Receiver<DebounceEventResult>,
+    dir: PathBuf,
+    extensions: Vec<String>,
+    // Stops watching when dropped.
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+impl Watcher {
+    /// `None` when the directory cannot be watched.
+    pub fn spawn(dir: &Path, extensions: Vec<String>) -> Option<Self> {
+        let (tx, rx) = mpsc::channel();
+        let mut debouncer = new_debouncer(DEBOUNCE, tx).ok()?;
+        debouncer.watcher().watch(dir, RecursiveMode::Recursive).ok()?;
+        Some(Self { rx, dir: dir.to_path_buf(), extensions, _debouncer: debouncer })
+    }
+
+    /// Changes since the last call, if any.
+    pub fn changed(&self) -> Option<Changes> {
+        let mut paths = Vec::new();
+        let mut lost = false;
+        for result in self.rx.try_iter() {
+            match result {
+                Ok(events) => paths.extend(events.into_iter().map(|e| e.path).filter(|p| self.relevant(p))),
+                Err(_) => lost = true,
+            }
+        }
+        if lost {
+            return Some(Changes::Unknown);
+        }
+        paths.sort();
+        paths.dedup();
+        (!paths.is_empty()).then_some(Changes::Paths(paths))
+    }
+
+    fn relevant(&self, path: &Path) -> bool {
+        let Ok(rel) = path.strip_prefix(&self.dir) else { return false };
+        let hidden = rel
+            .components()
+            .any(|c| matches!(c, Component::Normal(name) if name == ".git" || name == SIDECAR_DIR));
+        if hidden {
+            return false;
+        }
+        let tracked_extension = path
+            .extension()
+            .is_some_and(|ext| self.extensions.contains(&ext.to_string_lossy().to_lowercase()));
+        // An ignored directory hides everything under it, so every directory
+        // on the way must be walked too. A removed path can't be matched
+        // itself, only the directories above it.
+        let walked = |skip| path.ancestors().skip(skip).take_while(|p| *p != self.dir).all(search::is_walked);
+        if !path.exists() {
+            return walked(1);
+        }
+        (tracked_extension || path.is_dir()) && walked(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn wait_for_changes(watcher: &Watcher) -> Vec<PathBuf> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match watcher.changed() {
+                Some(Changes::Paths(paths)) => return paths,
+                Some(Changes::Unknown) => panic!("events were lost"),
+                None => std::thread::sleep(Duration::from_millis(50)),
+            }
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn reports_only_tracked_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let watcher = Watcher::spawn(&dir, vec!["md".to_string()]).unwrap();
+
+        std::fs::write(dir.join("notes.md"), "# Notes").unwrap();
+        std::fs::write(dir.join("build.log"), "noise").unwrap();
+        std::fs::create_dir(dir.join(SIDECAR_DIR)).unwrap();
+        std::fs::write(dir.join(SIDECAR_DIR).join("index.md"), "cache").unwrap();
+
+        assert_eq!(wait_for_changes(&watcher), vec![dir.join("notes.md")]);
+    }
+
+    #[test]
+    fn skips_ignored_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        std::fs::write(dir.join(".gitignore"), "drafts/\n").unwrap();
+        std::fs::write(dir.join(search::IGNORE_FILE), "scratch.md\n").unwrap();
+        std::fs::create_dir(dir.join("drafts")).unwrap();
+        let watcher = Watcher::spawn(&dir, vec!["md".to_string()]).unwrap();
+
+        std::fs::write(dir.join("drafts").join("idea.md"), "# Idea").unwrap();
+        std::fs::write(dir.join("scratch.md"), "# Scratch").unwrap();
+        std::fs::write(dir.join("notes.md"), "# Notes").unwrap();
+
+        assert_eq!(wait_for_changes(&watcher), vec![dir.join("notes.md")]);
+    }
+}